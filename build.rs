@@ -3,6 +3,8 @@ use std::fs;
 use std::path::Path;
 
 fn main() {
+    println!("cargo:rerun-if-changed=resources");
+
     let out_dir = env::var("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("../../../");
     let res_path = Path::new("resources");
@@ -23,4 +25,15 @@ fn main() {
         )
         .unwrap();
     }
+
+    let profiles_dir = res_path.join("profiles.d");
+    if profiles_dir.is_dir() {
+        let target_profiles_dir = target_resources_path.join("profiles.d");
+        fs::create_dir(&target_profiles_dir).expect("Failed to create profiles.d directory");
+
+        for entry in fs::read_dir(&profiles_dir).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, target_profiles_dir.join(path.file_name().unwrap())).unwrap();
+        }
+    }
 }
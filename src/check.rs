@@ -0,0 +1,77 @@
+//! `keymapper --check`: validates the profile files against the schema, loads them and prints
+//! the effective bindings of each profile, after inheritance and macro expansion.
+
+use std::fs;
+
+use crate::document;
+use crate::errors::AppError;
+use crate::migrate;
+use crate::profiles::{self, Trigger};
+use crate::schema;
+use crate::windows;
+
+/// Runs `keymapper --check` and returns the process exit code.
+pub fn run() -> i32 {
+    windows::attach_parent_console();
+
+    let valid = match validate_files() {
+        Ok(valid) => valid,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+
+    let profiles = match profiles::load_profiles() {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+
+    for profile in profiles.iter() {
+        match &profile.extends {
+            Some(parent) => println!("Profile \"{}\" extends \"{}\"", profile.name, parent),
+            None => println!("Profile \"{}\"", profile.name),
+        }
+
+        for trigger in &profile.triggers {
+            match trigger {
+                Trigger::Window { name } => println!("  window \"{}\"", name),
+            }
+        }
+
+        for binding in &profile.bindings {
+            if binding.actions().is_empty() {
+                println!("  {}: no actions", binding);
+            } else {
+                println!("  {}:", binding);
+                for action in binding.actions() {
+                    println!("    {}", action);
+                }
+            }
+        }
+    }
+
+    if valid {
+        0
+    } else {
+        1
+    }
+}
+
+/// Prints the schema problems of each profile file, returning true if there were none.
+fn validate_files() -> Result<bool, AppError> {
+    let mut valid = true;
+    for path in profiles::profile_files()? {
+        let mut root = document::read(&path, &fs::read_to_string(&path)?)?;
+        // older documents are checked as they load, upgraded
+        migrate::migrate(&mut root)?;
+        for problem in schema::validate(&root) {
+            eprintln!("{}: {}", path.display(), problem);
+            valid = false;
+        }
+    }
+    Ok(valid)
+}
@@ -0,0 +1,512 @@
+//! Local control endpoint speaking line-delimited JSON, one request and one response per line:
+//!
+//! ```text
+//! {"command":"status"}
+//! {"command":"disable","profile":"WoW"}
+//! {"command":"inject","event":{"device":"keyboard","vk_code":65}}
+//! ```
+//!
+//! After a `subscribe` request the connection receives engine events, one per line.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::engine::{Engine, EngineEvent, EngineState};
+use crate::errors::AppError;
+use crate::profiles::{self, parse_hex, parse_mouse_button};
+use crate::windows::{self, HookAction, InputEvent, KeyboardEvent, MouseEvent};
+
+#[cfg(windows)]
+const PIPE_NAME: &str = r"\\.\pipe\keymapper";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    Enable { profile: String },
+    Disable { profile: String },
+    Reload,
+    Inject { event: InputEvent },
+    Shutdown,
+    Subscribe,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    /// Whether an injected event was blocked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// All profiles, hotkeys excluded.
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    pub active_profiles: Vec<String>,
+    pub disabled_profiles: Vec<String>,
+    pub suspended: bool,
+    pub queue_depth: usize,
+    pub uptime_secs: u64,
+}
+
+impl Response {
+    fn ok() -> Response {
+        Response {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    fn error(err: AppError) -> Response {
+        Response {
+            ok: false,
+            error: Some(err.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Executes control requests against the running engine.
+pub struct ControlServer {
+    engine: Arc<Mutex<Engine>>,
+    state: Arc<EngineState>,
+    started: Instant,
+    main_thread_id: u32,
+}
+
+impl ControlServer {
+    /// The main thread runs the message loop, shutdown ends it.
+    pub fn new(
+        engine: Arc<Mutex<Engine>>,
+        state: Arc<EngineState>,
+        main_thread_id: u32,
+    ) -> ControlServer {
+        ControlServer {
+            engine,
+            state,
+            started: Instant::now(),
+            main_thread_id,
+        }
+    }
+
+    pub fn execute(&self, request: Request) -> Response {
+        match self.try_execute(request) {
+            Ok(response) => response,
+            Err(err) => Response::error(err),
+        }
+    }
+
+    fn try_execute(&self, request: Request) -> Result<Response, AppError> {
+        match request {
+            Request::Status => {
+                let engine = self.engine.lock().unwrap();
+                let status = Status {
+                    profiles: engine
+                        .profiles()
+                        .profiles
+                        .iter()
+                        .map(|profile| profile.name.clone())
+                        .collect(),
+                    active_profiles: engine.active_profiles(),
+                    disabled_profiles: self.state.disabled_profiles(),
+                    suspended: self.state.is_suspended(),
+                    queue_depth: engine.queue_depth(),
+                    uptime_secs: self.started.elapsed().as_secs(),
+                };
+                Ok(Response {
+                    status: Some(status),
+                    ..Response::ok()
+                })
+            }
+            Request::Enable { profile } => self.set_enabled(&profile, true),
+            Request::Disable { profile } => self.set_enabled(&profile, false),
+            Request::Reload => {
+                let profiles = profiles::load_profiles()?;
+                self.engine.lock().unwrap().reload(Arc::new(profiles));
+                log::info!("Profiles reloaded");
+                Ok(Response::ok())
+            }
+            Request::Inject { event } => {
+                log::debug!("Injecting {:?}", event);
+                let action = self.engine.lock().unwrap().handle(&event, Instant::now());
+                Ok(Response {
+                    blocked: Some(action == HookAction::Block),
+                    ..Response::ok()
+                })
+            }
+            Request::Shutdown => {
+                windows::post_thread_quit_message(self.main_thread_id);
+                Ok(Response::ok())
+            }
+            // events are streamed by the connection
+            Request::Subscribe => Ok(Response::ok()),
+        }
+    }
+
+    fn set_enabled(&self, name: &str, enabled: bool) -> Result<Response, AppError> {
+        let engine = self.engine.lock().unwrap();
+        if !engine.profiles().profiles.iter().any(|p| p.name == name) {
+            return Err(AppError::new(format!("Unknown profile: {}", name)));
+        }
+        self.state.set_enabled(name, enabled);
+        Ok(Response::ok())
+    }
+}
+
+pub async fn serve(server: Arc<ControlServer>) {
+    if let Err(err) = listen(server).await {
+        log::error!("Control endpoint failed: {}", err);
+    }
+}
+
+#[cfg(windows)]
+async fn listen(server: Arc<ControlServer>) -> Result<(), AppError> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut pipe = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(PIPE_NAME)?;
+    log::info!("Control endpoint listening on {}", PIPE_NAME);
+
+    loop {
+        pipe.connect().await?;
+        let connected = pipe;
+        pipe = ServerOptions::new().create(PIPE_NAME)?;
+        tokio::spawn(handle_connection(connected, server.clone()));
+    }
+}
+
+#[cfg(unix)]
+async fn listen(server: Arc<ControlServer>) -> Result<(), AppError> {
+    listen_on(&socket_path(), server).await
+}
+
+#[cfg(unix)]
+async fn listen_on(path: &std::path::Path, server: Arc<ControlServer>) -> Result<(), AppError> {
+    use tokio::net::UnixListener;
+
+    // a stale socket of a previous run would fail the bind
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    log::info!("Control endpoint listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(handle_connection(stream, server.clone()));
+    }
+}
+
+#[cfg(unix)]
+fn socket_path() -> std::path::PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("keymapper.sock")
+}
+
+async fn handle_connection<S>(stream: S, server: Arc<ControlServer>)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("Control connection failed: {}", err);
+                return;
+            }
+        };
+
+        let request = serde_json::from_str(&line);
+        let subscribe = matches!(request, Ok(Request::Subscribe));
+        let events = server.state.subscribe();
+
+        let response = match request {
+            Ok(request) => server.execute(request),
+            Err(err) => Response::error(AppError::new(format!("Invalid request: {}", err))),
+        };
+
+        let result = match write_line(&mut writer, &response).await {
+            Ok(_) if subscribe => stream_events(&mut writer, events).await,
+            result => result,
+        };
+
+        if let Err(err) = result {
+            log::error!("Control connection failed: {}", err);
+            return;
+        }
+    }
+}
+
+async fn stream_events<W>(
+    writer: &mut W,
+    mut events: tokio::sync::broadcast::Receiver<EngineEvent>,
+) -> Result<(), AppError>
+where
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        match events.recv().await {
+            Ok(event) => write_line(writer, &event).await?,
+            Err(RecvError::Lagged(count)) => log::warn!("Subscriber missed {} events", count),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn write_line<W, T>(writer: &mut W, value: &T) -> Result<(), AppError>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut text = serde_json::to_string(value).map_err(|e| AppError::new(e.to_string()))?;
+    text.push('\n');
+    writer.write_all(text.as_bytes()).await?;
+    Ok(())
+}
+
+/// Runs `keymapper ctl <command>` and returns the process exit code.
+pub fn run_client(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    let result = parse_request(args).and_then(|request| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(send_request(&request))
+    });
+
+    match result {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Prints the response, followed by the events of a subscription.
+async fn send_request(request: &Request) -> Result<(), AppError> {
+    let stream = connect().await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    write_line(&mut writer, request).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let line = lines
+        .next_line()
+        .await?
+        .ok_or_else(|| AppError::new("Keymapper closed the connection"))?;
+
+    let response: Response =
+        serde_json::from_str(&line).map_err(|e| AppError::new(e.to_string()))?;
+    if let Some(error) = response.error {
+        return Err(AppError::new(error));
+    }
+    println!("{}", line);
+
+    if let Request::Subscribe = request {
+        while let Some(line) = lines.next_line().await? {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn connect() -> Result<tokio::net::windows::named_pipe::NamedPipeClient, AppError> {
+    let client = tokio::net::windows::named_pipe::ClientOptions::new().open(PIPE_NAME)?;
+    Ok(client)
+}
+
+#[cfg(unix)]
+async fn connect() -> Result<tokio::net::UnixStream, AppError> {
+    let stream = tokio::net::UnixStream::connect(socket_path()).await?;
+    Ok(stream)
+}
+
+const USAGE: &str = "Usage: keymapper ctl status | reload | shutdown | subscribe | enable <profile> | disable <profile>
+       keymapper ctl inject key <vk_code> [up] | button <name> [up] | wheel <delta> | hwheel <delta> | <json>";
+
+fn parse_request(args: &[String]) -> Result<Request, AppError> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let request = match args.as_slice() {
+        ["status"] => Request::Status,
+        ["reload"] => Request::Reload,
+        ["shutdown"] => Request::Shutdown,
+        ["subscribe"] => Request::Subscribe,
+        ["enable", profile] => Request::Enable {
+            profile: profile.to_string(),
+        },
+        ["disable", profile] => Request::Disable {
+            profile: profile.to_string(),
+        },
+        ["inject", rest @ ..] => Request::Inject {
+            event: parse_event(rest)?,
+        },
+        _ => return Err(AppError::new(USAGE)),
+    };
+    Ok(request)
+}
+
+fn parse_event(args: &[&str]) -> Result<InputEvent, AppError> {
+    let up = |flag: &[&str]| match flag {
+        [] => Ok(false),
+        ["up"] => Ok(true),
+        _ => Err(AppError::new(USAGE)),
+    };
+    let delta = |text: &str| {
+        text.parse::<i16>()
+            .map_err(|_| AppError::new(format!("Invalid wheel delta {}", text)))
+    };
+
+    let event = match args {
+        ["key", vk_code, flag @ ..] => {
+            InputEvent::Keyboard(KeyboardEvent::new(parse_hex(vk_code)?, up(flag)?))
+        }
+        ["button", button, flag @ ..] => InputEvent::Mouse(MouseEvent::Button {
+            x: 0,
+            y: 0,
+            button: parse_mouse_button(button)?,
+            up: up(flag)?,
+            extra: 0,
+        }),
+        ["wheel", text] => InputEvent::Mouse(MouseEvent::MouseWheel {
+            x: 0,
+            y: 0,
+            delta: delta(text)?,
+            extra: 0,
+        }),
+        ["hwheel", text] => InputEvent::Mouse(MouseEvent::MouseHWheel {
+            x: 0,
+            y: 0,
+            delta: delta(text)?,
+            extra: 0,
+        }),
+        [json] => serde_json::from_str(json)
+            .map_err(|e| AppError::new(format!("Invalid event: {}", e)))?,
+        _ => return Err(AppError::new(USAGE)),
+    };
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_ctl_requests() {
+        assert!(matches!(
+            parse_request(&args("status")).unwrap(),
+            Request::Status
+        ));
+        assert!(matches!(
+            parse_request(&args("disable WoW")).unwrap(),
+            Request::Disable { profile } if profile == "WoW"
+        ));
+        assert!(parse_request(&args("enable")).is_err());
+
+        match parse_request(&args("inject key 0x41 up")).unwrap() {
+            Request::Inject {
+                event: InputEvent::Keyboard(e),
+            } => assert!(e.vk_code == 0x41 && e.up() && !e.syntetic()),
+            other => panic!("unexpected request {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_inject_request() {
+        let request: Request = serde_json::from_str(
+            r#"{"command":"inject","event":{"device":"mouse","type":"button","button":"x1"}}"#,
+        )
+        .unwrap();
+
+        match request {
+            Request::Inject {
+                event: InputEvent::Mouse(MouseEvent::Button { button, up, .. }),
+            } => assert!(button == windows::MouseButton::X1 && !up),
+            other => panic!("unexpected request {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serve_requests_on_socket() {
+        use crate::engine::MACRO_QUEUE_SIZE;
+        use crate::filter::ChatterFilter;
+        use crate::profiles::parse_profiles;
+        use tokio::sync::mpsc;
+
+        let profiles =
+            parse_profiles(r#"<profiles><profile name="WoW"/><profile name="MK11"/></profiles>"#)
+                .unwrap();
+        let state = Arc::new(EngineState::default());
+        let (tx, _rx) = mpsc::channel(MACRO_QUEUE_SIZE);
+        let filter = ChatterFilter::new(&Default::default()).unwrap();
+        let engine = Arc::new(Mutex::new(Engine::new(
+            Arc::new(profiles),
+            state.clone(),
+            filter,
+            tx,
+        )));
+        let server = Arc::new(ControlServer::new(engine, state.clone(), 0));
+
+        let path = std::env::temp_dir().join(format!("keymapper-{}.sock", std::process::id()));
+        tokio::spawn({
+            let path = path.clone();
+            async move { listen_on(&path, server).await.unwrap() }
+        });
+
+        let mut stream = None;
+        for _ in 0..100 {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        }
+        let (reader, mut writer) = tokio::io::split(stream.expect("listening socket"));
+        let mut lines = BufReader::new(reader).lines();
+        let mut responses = Vec::new();
+        for request in &[
+            r#"{"command":"disable","profile":"MK11"}"#,
+            r#"{"command":"enable","profile":"Unknown"}"#,
+            "not json",
+            r#"{"command":"status"}"#,
+        ] {
+            writer.write_all(request.as_bytes()).await.unwrap();
+            writer.write_all(b"\n").await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            responses.push(serde_json::from_str::<Response>(&line).unwrap());
+        }
+
+        assert!(responses[0].ok);
+        assert!(!responses[1].ok && responses[1].error.is_some());
+        assert!(!responses[2].ok);
+        let status = responses[3].status.as_ref().unwrap();
+        assert_eq!(status.profiles, vec!["WoW", "MK11"]);
+        assert_eq!(status.disabled_profiles, vec!["MK11"]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,362 @@
+//! D-Bus service on the session bus, for panel widgets on Linux desktops. It executes the
+//! requests of the control endpoint and turns engine events into signals:
+//!
+//! ```text
+//! busctl --user call org.keymapper.Keymapper /org/keymapper/Keymapper \
+//!     org.keymapper.Keymapper1 ToggleProfile s WoW
+//! ```
+
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use zbus::object_server::SignalEmitter;
+use zbus::{connection, fdo, interface};
+
+use crate::control::{ControlServer, Request, Response};
+use crate::engine::{EngineEvent, EngineState};
+
+pub const BUS_NAME: &str = "org.keymapper.Keymapper";
+pub const OBJECT_PATH: &str = "/org/keymapper/Keymapper";
+
+struct Service {
+    server: Arc<ControlServer>,
+    state: Arc<EngineState>,
+}
+
+impl Service {
+    fn execute(&self, request: Request) -> fdo::Result<Response> {
+        let response = self.server.execute(request);
+        match response.error {
+            Some(error) => Err(fdo::Error::Failed(error)),
+            None => Ok(response),
+        }
+    }
+
+    fn set_enabled(&self, profile: &str, enabled: bool) -> fdo::Result<()> {
+        let profile = profile.to_string();
+        let request = match enabled {
+            true => Request::Enable { profile },
+            false => Request::Disable { profile },
+        };
+        self.execute(request).map(|_| ())
+    }
+}
+
+#[interface(name = "org.keymapper.Keymapper1")]
+impl Service {
+    /// Name, enabled and active state of each profile.
+    fn list_profiles(&self) -> fdo::Result<Vec<(String, bool, bool)>> {
+        let status = self
+            .execute(Request::Status)?
+            .status
+            .ok_or_else(|| fdo::Error::Failed("No status".to_string()))?;
+
+        let (disabled, active) = (status.disabled_profiles, status.active_profiles);
+        Ok(status
+            .profiles
+            .into_iter()
+            .map(|name| {
+                let enabled = !disabled.contains(&name);
+                let active = active.contains(&name);
+                (name, enabled, active)
+            })
+            .collect())
+    }
+
+    fn enable_profile(&self, profile: &str) -> fdo::Result<()> {
+        self.set_enabled(profile, true)
+    }
+
+    fn disable_profile(&self, profile: &str) -> fdo::Result<()> {
+        self.set_enabled(profile, false)
+    }
+
+    /// Enables a disabled profile or disables an enabled one, returns whether it's enabled.
+    fn toggle_profile(&self, profile: &str) -> fdo::Result<bool> {
+        let enabled = !self.state.is_enabled(profile);
+        self.set_enabled(profile, enabled)?;
+        Ok(enabled)
+    }
+
+    fn reload(&self) -> fdo::Result<()> {
+        self.execute(Request::Reload).map(|_| ())
+    }
+
+    #[zbus(signal)]
+    async fn profile_activated(emitter: &SignalEmitter<'_>, profile: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn profile_deactivated(emitter: &SignalEmitter<'_>, profile: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn profile_enabled(
+        emitter: &SignalEmitter<'_>,
+        profile: &str,
+        enabled: bool,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn binding_fired(
+        emitter: &SignalEmitter<'_>,
+        profile: &str,
+        binding: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn suspended(emitter: &SignalEmitter<'_>, suspended: bool) -> zbus::Result<()>;
+}
+
+/// Serves on the session bus until the engine state goes away.
+pub async fn serve(server: Arc<ControlServer>, state: Arc<EngineState>) {
+    let result = match connection::Builder::session() {
+        Ok(builder) => listen(builder, server, state).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        log::error!("D-Bus service failed: {}", err);
+    }
+}
+
+async fn listen(
+    builder: connection::Builder<'_>,
+    server: Arc<ControlServer>,
+    state: Arc<EngineState>,
+) -> zbus::Result<()> {
+    let mut events = state.subscribe();
+    let connection = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Service { server, state })?
+        .build()
+        .await?;
+    log::info!("D-Bus service {} started", BUS_NAME);
+
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+    loop {
+        match events.recv().await {
+            Ok(event) => emit(&emitter, event).await?,
+            Err(RecvError::Lagged(count)) => log::warn!("D-Bus service missed {} events", count),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn emit(emitter: &SignalEmitter<'_>, event: EngineEvent) -> zbus::Result<()> {
+    match event {
+        EngineEvent::Suspended { suspended } => Service::suspended(emitter, suspended).await,
+        // forcing shows in the activation signals
+        EngineEvent::ProfileForced { .. } => Ok(()),
+        EngineEvent::ProfileActivated { profile } => {
+            Service::profile_activated(emitter, &profile).await
+        }
+        EngineEvent::ProfileDeactivated { profile } => {
+            Service::profile_deactivated(emitter, &profile).await
+        }
+        EngineEvent::ProfileEnabled { profile, enabled } => {
+            Service::profile_enabled(emitter, &profile, enabled).await
+        }
+        EngineEvent::BindingFired { profile, binding } => {
+            Service::binding_fired(emitter, &profile, &binding).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+    use std::io::{BufRead, BufReader};
+    use std::pin::Pin;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use zbus::export::futures_core::Stream;
+    use zbus::{Connection, Proxy};
+
+    use super::*;
+    use crate::engine::{Engine, MACRO_QUEUE_SIZE};
+    use crate::filter::ChatterFilter;
+    use crate::profiles::parse_profiles;
+    use crate::windows::{InputEvent, KeyboardEvent};
+
+    const CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+    /// A private bus, stopped when dropped.
+    struct Daemon {
+        child: Child,
+        address: String,
+    }
+
+    impl Daemon {
+        /// None if there is no dbus-daemon to run.
+        fn start() -> Option<Daemon> {
+            let config =
+                std::env::temp_dir().join(format!("keymapper-dbus-{}.conf", std::process::id()));
+            std::fs::write(&config, CONFIG).unwrap();
+
+            let mut child = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--print-address=1", "--nofork"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(child.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+
+            Some(Daemon {
+                child,
+                address: address.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    async fn next<S: Stream + Unpin>(stream: &mut S) -> S::Item {
+        let item = future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx));
+        timeout(Duration::from_secs(5), item)
+            .await
+            .expect("signal in time")
+            .expect("open stream")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serve_profiles_and_signals() {
+        let daemon = match Daemon::start() {
+            Some(daemon) => daemon,
+            None => {
+                eprintln!("dbus-daemon is not available, skipped");
+                return;
+            }
+        };
+
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="WoW">
+                    <bindings>
+                        <binding name="jump" vk_code="0x41"><key vk_code="0x20"/></binding>
+                    </bindings>
+                </profile>
+                <profile name="MK11"/>
+            </profiles>"#,
+        )
+        .unwrap();
+        let state = Arc::new(EngineState::default());
+        let (tx, _rx) = mpsc::channel(MACRO_QUEUE_SIZE);
+        let filter = ChatterFilter::new(&Default::default()).unwrap();
+        let engine = Arc::new(Mutex::new(Engine::new(
+            Arc::new(profiles),
+            state.clone(),
+            filter,
+            tx,
+        )));
+        let server = Arc::new(ControlServer::new(engine.clone(), state.clone(), 0));
+
+        let builder = connection::Builder::address(daemon.address.as_str()).unwrap();
+        tokio::spawn(listen(builder, server, state.clone()));
+
+        let client = connection::Builder::address(daemon.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = proxy(&client).await;
+
+        let mut enabled = proxy.receive_signal("ProfileEnabled").await.unwrap();
+        let mut fired = proxy.receive_signal("BindingFired").await.unwrap();
+        let mut activated = proxy.receive_signal("ProfileActivated").await.unwrap();
+
+        let list = |proxy: Proxy<'static>| async move {
+            proxy
+                .call::<_, _, Vec<(String, bool, bool)>>("ListProfiles", &())
+                .await
+                .unwrap()
+        };
+        assert_eq!(
+            list(proxy.clone()).await,
+            vec![
+                ("WoW".to_string(), true, false),
+                ("MK11".to_string(), true, false)
+            ]
+        );
+
+        let toggled: bool = proxy.call("ToggleProfile", &("MK11",)).await.unwrap();
+        assert!(!toggled);
+        let (profile, enabled): (String, bool) =
+            next(&mut enabled).await.body().deserialize().unwrap();
+        assert_eq!((profile.as_str(), enabled), ("MK11", false));
+        assert!(proxy
+            .call::<_, _, ()>("DisableProfile", &("Unknown",))
+            .await
+            .is_err());
+
+        state.execute(
+            &crate::profiles::ControlCommand::ActivateProfile(Some("WoW".to_string())),
+            &engine.lock().unwrap().profiles().clone(),
+        );
+        let key = InputEvent::Keyboard(KeyboardEvent::new(0x41, false));
+        engine.lock().unwrap().handle(&key, Instant::now());
+
+        let profile: String = next(&mut activated).await.body().deserialize().unwrap();
+        assert_eq!(profile, "WoW");
+        let (profile, binding): (String, String) =
+            next(&mut fired).await.body().deserialize().unwrap();
+        assert_eq!(
+            (profile.as_str(), binding.as_str()),
+            ("WoW", "binding \"jump\"")
+        );
+        assert_eq!(
+            list(proxy.clone()).await,
+            vec![
+                ("WoW".to_string(), true, true),
+                ("MK11".to_string(), false, false)
+            ]
+        );
+    }
+
+    /// Waits for the service to own its name.
+    async fn proxy(connection: &Connection) -> Proxy<'static> {
+        let bus = fdo::DBusProxy::new(connection).await.unwrap();
+        let started = Instant::now();
+        while !bus
+            .name_has_owner(zbus::names::BusName::from_static_str(BUS_NAME).unwrap())
+            .await
+            .unwrap()
+        {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "service started"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        Proxy::new(
+            connection,
+            BUS_NAME,
+            OBJECT_PATH,
+            "org.keymapper.Keymapper1",
+        )
+        .await
+        .unwrap()
+    }
+}
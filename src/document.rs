@@ -0,0 +1,781 @@
+//! Profile documents in XML, TOML or YAML, chosen by file extension. TOML and YAML files are
+//! converted into the same element tree the XML reader produces, so all formats load the
+//! same profiles. XML comments become the `comment` of the element after them, or a node of
+//! their own at the end of a list.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use xml::*;
+
+use crate::errors::AppError;
+use crate::profiles;
+use crate::windows;
+
+const CONVERT_USAGE: &str = "Usage: keymapper convert [--expand] <input> <output>";
+const FMT_USAGE: &str = "Usage: keymapper fmt [--check] [<file>...]";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Xml,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "xml" => Some(Format::Xml),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Profiles root. Root attributes such as `fps` sit next to the lists.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Document {
+    #[serde(flatten)]
+    pub attributes: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vars: Vec<Var>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<Node>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hotkeys: Vec<Node>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<ProfileNode>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileNode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(flatten)]
+    pub attributes: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vars: Vec<Var>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<Node>,
+    #[serde(
+        rename = "motion-input",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub motion_input: Option<Node>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bindings: Vec<Node>,
+}
+
+/// Variable, in a list since values can use the variables before them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Var {
+    pub name: String,
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl Var {
+    fn from_element(e: &Element, comment: Option<String>) -> Result<Var, AppError> {
+        let name = e
+            .get_attribute("name", None)
+            .ok_or_else(|| AppError::new("name is missing from var"))?;
+        let value = e
+            .get_attribute("value", None)
+            .ok_or_else(|| AppError::new(format!("value is missing from var {}", name)))?;
+        drop_comments(e);
+        Ok(Var {
+            name: name.to_string(),
+            value: Value::from_text(value),
+            comment,
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::new(
+            "var".to_string(),
+            None,
+            vec![
+                ("name".to_string(), None, self.name.clone()),
+                ("value".to_string(), None, self.value.to_text()),
+            ],
+        )
+    }
+}
+
+/// Any other element: a binding, action, trigger or macro definition, named by `type`. A node
+/// without a type is only a comment, and has nothing else.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(rename = "type", default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(flatten)]
+    pub attributes: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Node>,
+}
+
+/// Attribute value. XML attributes are all text, the other formats may also use plain
+/// numbers and booleans.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Value {
+    /// Numbers and booleans whose text reads back unchanged are written as such.
+    fn from_text(text: &str) -> Value {
+        if let Ok(value) = text.parse::<bool>() {
+            return Value::Bool(value);
+        }
+        match text.parse::<i64>() {
+            Ok(value) if value.to_string() == text => Value::Integer(value),
+            _ => Value::Text(text.to_string()),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Value::Bool(value) => value.to_string(),
+            Value::Integer(value) => value.to_string(),
+            Value::Float(value) => value.to_string(),
+            Value::Text(value) => value.clone(),
+        }
+    }
+}
+
+impl Document {
+    pub fn from_element(root: &Element) -> Result<Document, AppError> {
+        let mut document = Document {
+            attributes: attributes_of(root),
+            ..Document::default()
+        };
+
+        let mut comments = vec![];
+        for node in &root.children {
+            let e = match node {
+                Xml::ElementNode(e) => e,
+                Xml::CommentNode(comment) => {
+                    comments.push(comment.clone());
+                    continue;
+                }
+                _ => continue,
+            };
+            match e.name.as_ref() {
+                "var" => document
+                    .vars
+                    .push(Var::from_element(e, join(&mut comments))?),
+                "macros" => document.macros.extend(nodes(e, &mut comments)),
+                "hotkeys" => document.hotkeys.extend(nodes(e, &mut comments)),
+                "profile" => document
+                    .profiles
+                    .push(ProfileNode::from_element(e, join(&mut comments))?),
+                _ => return Err(AppError::new(format!("Unknown element: {}", e.name))),
+            }
+        }
+        comments.iter().for_each(|comment| drop_comment(comment));
+
+        Ok(document)
+    }
+
+    pub fn to_element(&self) -> Result<Element, AppError> {
+        let mut root = element("profiles", &self.attributes);
+        for var in &self.vars {
+            push_element(&mut root, &var.comment, var.to_element());
+        }
+        if !self.macros.is_empty() {
+            root.children
+                .push(Xml::ElementNode(section("macros", &self.macros, "macros")?));
+        }
+        if !self.hotkeys.is_empty() {
+            root.children.push(Xml::ElementNode(section(
+                "hotkeys",
+                &self.hotkeys,
+                "hotkeys",
+            )?));
+        }
+        for (i, profile) in self.profiles.iter().enumerate() {
+            let e = profile.to_element(&format!("profiles[{}]", i))?;
+            push_element(&mut root, &profile.comment, e);
+        }
+        Ok(root)
+    }
+}
+
+impl ProfileNode {
+    fn from_element(e: &Element, comment: Option<String>) -> Result<ProfileNode, AppError> {
+        let mut profile = ProfileNode {
+            comment,
+            attributes: attributes_of(e),
+            ..ProfileNode::default()
+        };
+
+        let mut comments = vec![];
+        for node in &e.children {
+            let child = match node {
+                Xml::ElementNode(child) => child,
+                Xml::CommentNode(comment) => {
+                    comments.push(comment.clone());
+                    continue;
+                }
+                _ => continue,
+            };
+            match child.name.as_ref() {
+                "var" => profile
+                    .vars
+                    .push(Var::from_element(child, join(&mut comments))?),
+                "triggers" => profile.triggers.extend(nodes(child, &mut comments)),
+                "motion-input" => {
+                    profile.motion_input = Some(Node::from_element(child, join(&mut comments)))
+                }
+                "bindings" => profile.bindings.extend(nodes(child, &mut comments)),
+                _ => {
+                    return Err(AppError::new(format!(
+                        "Unknown profile element: {}",
+                        child.name
+                    )))
+                }
+            }
+        }
+        comments.iter().for_each(|comment| drop_comment(comment));
+
+        Ok(profile)
+    }
+
+    fn to_element(&self, path: &str) -> Result<Element, AppError> {
+        let mut e = element("profile", &self.attributes);
+        for var in &self.vars {
+            push_element(&mut e, &var.comment, var.to_element());
+        }
+        if !self.triggers.is_empty() {
+            let triggers = section("triggers", &self.triggers, &format!("{}.triggers", path))?;
+            e.children.push(Xml::ElementNode(triggers));
+        }
+        if let Some(motion_input) = &self.motion_input {
+            motion_input.push_to(&mut e, &format!("{}.motion-input", path))?;
+        }
+        if !self.bindings.is_empty() {
+            let bindings = section("bindings", &self.bindings, &format!("{}.bindings", path))?;
+            e.children.push(Xml::ElementNode(bindings));
+        }
+        Ok(e)
+    }
+}
+
+impl Node {
+    fn from_element(e: &Element, comment: Option<String>) -> Node {
+        Node {
+            name: e.name.clone(),
+            comment,
+            attributes: attributes_of(e),
+            children: nodes(e, &mut vec![]),
+        }
+    }
+
+    fn comment(comment: String) -> Node {
+        Node {
+            comment: Some(comment),
+            ..Node::default()
+        }
+    }
+
+    fn to_element(&self, path: &str) -> Result<Element, AppError> {
+        let mut e = element(&self.name, &self.attributes);
+        for (i, child) in self.children.iter().enumerate() {
+            child.push_to(&mut e, &format!("{}.children[{}]", path, i))?;
+        }
+        Ok(e)
+    }
+
+    /// Adds the comment and element of the node to the children of the parent. `path` locates
+    /// the node in the document for errors.
+    fn push_to(&self, parent: &mut Element, path: &str) -> Result<(), AppError> {
+        if !self.name.is_empty() {
+            push_element(parent, &self.comment, self.to_element(path)?);
+            return Ok(());
+        }
+
+        match &self.comment {
+            Some(comment) if self.attributes.is_empty() && self.children.is_empty() => {
+                parent.children.push(Xml::CommentNode(comment.clone()));
+                Ok(())
+            }
+            _ => Err(AppError::new(format!("{} has no type", path))),
+        }
+    }
+}
+
+/// Child elements as nodes, each with the comment right before it. Comments before the
+/// element, such as those before a `<bindings>` section, lead its first child. Other
+/// comments become nodes of their own.
+fn nodes(e: &Element, comments: &mut Vec<String>) -> Vec<Node> {
+    let mut nodes = vec![];
+    for node in &e.children {
+        match node {
+            Xml::ElementNode(child) => {
+                let comment = comments.pop();
+                nodes.extend(comments.drain(..).map(Node::comment));
+                nodes.push(Node::from_element(child, comment));
+            }
+            Xml::CommentNode(comment) => comments.push(comment.clone()),
+            _ => {}
+        }
+    }
+    nodes.extend(comments.drain(..).map(Node::comment));
+    nodes
+}
+
+/// Comments before an element that holds a single comment, joined by lines.
+fn join(comments: &mut Vec<String>) -> Option<String> {
+    match comments.is_empty() {
+        true => None,
+        false => Some(mem::take(comments).join("\n")),
+    }
+}
+
+fn push_element(parent: &mut Element, comment: &Option<String>, e: Element) {
+    parent
+        .children
+        .extend(comment.clone().map(Xml::CommentNode));
+    parent.children.push(Xml::ElementNode(e));
+}
+
+fn drop_comments(e: &Element) {
+    for node in &e.children {
+        match node {
+            Xml::CommentNode(comment) => drop_comment(comment),
+            Xml::ElementNode(child) => drop_comments(child),
+            _ => {}
+        }
+    }
+}
+
+fn drop_comment(comment: &str) {
+    log::warn!(
+        "Dropped comment <!--{}-->, it has no element to go with",
+        comment
+    );
+}
+
+/// Number of comments in the element and its children.
+fn count_comments(e: &Element) -> usize {
+    e.children
+        .iter()
+        .map(|node| match node {
+            Xml::CommentNode(_) => 1,
+            Xml::ElementNode(child) => count_comments(child),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Reads a profile file in the format of its extension.
+pub fn read(path: &Path, text: &str) -> Result<Element, AppError> {
+    let error = |err: String| AppError::new(format!("Error parsing {}: {}", path.display(), err));
+
+    match Format::from_path(path) {
+        Some(Format::Xml) => text
+            .parse()
+            .map_err(|err: BuilderError| error(err.to_string())),
+        Some(Format::Toml) => toml::from_str::<Document>(text)
+            .map_err(|err| error(err.to_string()))?
+            .to_element()
+            .map_err(|err| error(err.to_string())),
+        Some(Format::Yaml) => serde_yaml::from_str::<Document>(text)
+            .map_err(|err| error(err.to_string()))?
+            .to_element()
+            .map_err(|err| error(err.to_string())),
+        None => Err(AppError::new(format!(
+            "Unknown profile format: {}",
+            path.display()
+        ))),
+    }
+}
+
+/// Writes a profiles element in the given format.
+pub fn write(root: &Element, format: Format) -> Result<String, AppError> {
+    match format {
+        Format::Xml => Ok(to_xml(root)),
+        Format::Toml => toml::to_string_pretty(&Document::from_element(root)?)
+            .map_err(|err| AppError::new(err.to_string())),
+        Format::Yaml => serde_yaml::to_string(&Document::from_element(root)?)
+            .map_err(|err| AppError::new(err.to_string())),
+    }
+}
+
+/// Attributes that identify an element come first, in this order, the rest by name.
+const LEADING_ATTRIBUTES: &[&str] = &[
+    "version",
+    "name",
+    "id",
+    "ref",
+    "extends",
+    "vk_code",
+    "button",
+    "direction",
+    "horizontal",
+    "up",
+    "down",
+    "left",
+    "right",
+    "alt",
+    "notation",
+    "keys",
+    "program",
+    "hold",
+    "gap",
+];
+
+/// Canonical XML: four space indents, attributes in a fixed order, comments kept and
+/// blank lines between elements collapsed to one.
+pub fn to_xml(root: &Element) -> String {
+    let mut out = String::new();
+    write_xml(root, 0, &mut out);
+    out
+}
+
+fn write_xml(e: &Element, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!("{}<{}", indent, e.name));
+
+    let mut attributes: Vec<_> = e
+        .attributes
+        .iter()
+        .map(|((name, _), value)| (name.as_str(), value))
+        .collect();
+    attributes.sort_by_key(|(name, _)| {
+        let position = LEADING_ATTRIBUTES
+            .iter()
+            .position(|leading| leading == name);
+        (position.unwrap_or(LEADING_ATTRIBUTES.len()), *name)
+    });
+    for (name, value) in attributes {
+        out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+    }
+
+    let children: Vec<_> = e
+        .children
+        .iter()
+        .filter(|node| !matches!(node, Xml::CharacterNode(text) if text.trim().is_empty()))
+        .collect();
+    if children.is_empty() {
+        out.push_str("/>\n");
+        return;
+    }
+
+    out.push_str(">\n");
+    let mut blank_line = false;
+    for (i, node) in e.children.iter().enumerate() {
+        match node {
+            Xml::CharacterNode(text) if text.trim().is_empty() => {
+                // only between two children, not after the opening or before the closing tag
+                let inner = i > 0 && i + 1 < e.children.len();
+                blank_line |= inner && text.matches('\n').count() > 1;
+                continue;
+            }
+            _ if blank_line => out.push('\n'),
+            _ => {}
+        }
+        blank_line = false;
+
+        let child_indent = "    ".repeat(depth + 1);
+        match node {
+            Xml::ElementNode(child) => write_xml(child, depth + 1, out),
+            Xml::CommentNode(comment) => {
+                out.push_str(&format!("{}<!--{}-->\n", child_indent, comment))
+            }
+            Xml::CharacterNode(text) => {
+                out.push_str(&format!("{}{}\n", child_indent, escape(text.trim())))
+            }
+            Xml::CDATANode(text) => {
+                out.push_str(&format!("{}<![CDATA[{}]]>\n", child_indent, text))
+            }
+            Xml::PINode(text) => out.push_str(&format!("{}<?{}?>\n", child_indent, text)),
+        }
+    }
+    out.push_str(&format!("{}</{}>\n", indent, e.name));
+}
+
+/// Runs `keymapper convert` and returns the process exit code.
+pub fn run_convert(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    match convert(args) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn convert(args: &[String]) -> Result<(), AppError> {
+    let (expand, args) = match args {
+        [flag, args @ ..] if flag == "--expand" => (true, args),
+        _ => (false, args),
+    };
+    let (input, output) = match args {
+        [input, output] => (Path::new(input), Path::new(output)),
+        _ => return Err(AppError::new(CONVERT_USAGE)),
+    };
+    let format = Format::from_path(output)
+        .ok_or_else(|| AppError::new(format!("Unknown profile format: {}", output.display())))?;
+
+    let text = fs::read_to_string(input)?;
+    // never convert a file that would fail to load
+    let loaded = profiles::parse_sources(&[(input.to_path_buf(), text.clone())])?;
+
+    let root = if expand {
+        let comments = count_comments(&read(input, &text)?);
+        if comments > 0 {
+            log::warn!(
+                "Dropped {} comments, --expand writes the loaded profiles",
+                comments
+            );
+        }
+        profiles::write_profiles(&loaded)
+    } else {
+        read(input, &text)?
+    };
+    fs::write(output, write(&root, format)?)?;
+    Ok(())
+}
+
+/// Runs `keymapper fmt` and returns the process exit code.
+pub fn run_fmt(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    match fmt(args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Formats the given profile files, or all loaded ones. With `--check` only lists the files
+/// that are not formatted, returning false if there are any.
+fn fmt(args: &[String]) -> Result<bool, AppError> {
+    let (check, args) = match args {
+        [flag, args @ ..] if flag == "--check" => (true, args),
+        _ => (false, args),
+    };
+    if args.iter().any(|arg| arg.starts_with("--")) {
+        return Err(AppError::new(FMT_USAGE));
+    }
+    let paths = if args.is_empty() {
+        profiles::profile_files()?
+    } else {
+        args.iter().map(PathBuf::from).collect()
+    };
+
+    let mut formatted = true;
+    for path in paths {
+        let text = fs::read_to_string(&path)?;
+        let format = Format::from_path(&path)
+            .ok_or_else(|| AppError::new(format!("Unknown profile format: {}", path.display())))?;
+        let canonical = write(&read(&path, &text)?, format)?;
+        if canonical == text {
+            continue;
+        }
+
+        formatted = false;
+        if check {
+            println!("{} is not formatted", path.display());
+        } else {
+            fs::write(&path, canonical)?;
+            println!("Formatted {}", path.display());
+        }
+    }
+
+    Ok(formatted || !check)
+}
+
+fn attributes_of(e: &Element) -> BTreeMap<String, Value> {
+    e.attributes
+        .iter()
+        .map(|((name, _), value)| (name.clone(), Value::from_text(value)))
+        .collect()
+}
+
+fn element(name: &str, attributes: &BTreeMap<String, Value>) -> Element {
+    Element::new(
+        name.to_string(),
+        None,
+        attributes
+            .iter()
+            .map(|(name, value)| (name.clone(), None, value.to_text())),
+    )
+}
+
+fn section(name: &str, nodes: &[Node], path: &str) -> Result<Element, AppError> {
+    let mut e = Element::new(name.to_string(), None, vec![]);
+    for (i, node) in nodes.iter().enumerate() {
+        node.push_to(&mut e, &format!("{}[{}]", path, i))?;
+    }
+    Ok(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shipped_profiles() -> Element {
+        let path = Path::new("resources/profiles.xml");
+        read(path, &fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// The element without the whitespace between its children, which only XML keeps.
+    fn without_whitespace(e: &Element) -> Element {
+        let mut e = e.clone();
+        e.children.retain(|node| match node {
+            Xml::CharacterNode(text) => !text.trim().is_empty(),
+            _ => true,
+        });
+        for node in e.children.iter_mut() {
+            if let Xml::ElementNode(child) = node {
+                *child = without_whitespace(child);
+            }
+        }
+        e
+    }
+
+    #[test]
+    fn convert_without_loss() {
+        let root = shipped_profiles();
+        let expected = to_xml(&without_whitespace(&root));
+
+        for (format, path) in &[
+            (Format::Toml, "profiles.toml"),
+            (Format::Yaml, "profiles.yaml"),
+        ] {
+            let text = write(&root, *format).unwrap();
+            let converted = read(Path::new(path), &text).unwrap();
+            assert_eq!(to_xml(&converted), expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn keep_comments_in_toml() {
+        let xml = r#"<profiles>
+            <!-- Bindings of every game -->
+            <hotkeys>
+                <binding vk_code="0x13">
+                    <!-- Pause -->
+                    <!-- or resume -->
+                    <toggle-suspend/>
+                </binding>
+                <binding vk_code="0x5B">
+                    <!-- Block Win -->
+                </binding>
+            </hotkeys>
+            <!-- WoW -->
+            <profile name="WoW"/>
+            <!-- nothing to go with -->
+        </profiles>"#;
+        let root = read(Path::new("profiles.xml"), xml).unwrap();
+
+        let toml = write(&root, Format::Toml).unwrap();
+        assert!(toml.contains(r#"comment = " WoW ""#), "{}", toml);
+        let converted = read(Path::new("profiles.toml"), &toml).unwrap();
+        assert_eq!(
+            to_xml(&converted),
+            r#"<profiles>
+    <hotkeys>
+        <!-- Bindings of every game -->
+        <binding vk_code="0x13">
+            <!-- Pause -->
+            <!-- or resume -->
+            <toggle-suspend/>
+        </binding>
+        <binding vk_code="0x5B">
+            <!-- Block Win -->
+        </binding>
+    </hotkeys>
+    <!-- WoW -->
+    <profile name="WoW"/>
+</profiles>
+"#
+        );
+    }
+
+    #[test]
+    fn shipped_profiles_are_formatted() {
+        let text = fs::read_to_string("resources/profiles.xml").unwrap();
+        assert_eq!(to_xml(&shipped_profiles()), text);
+    }
+
+    #[test]
+    fn read_toml_values() {
+        let text = r#"
+            fps = 30
+
+            [[profiles]]
+            name = "Test"
+            vars = [{ name = "key", value = "0x32" }]
+            triggers = [{ type = "window", name = "Test" }]
+
+            [[profiles.bindings]]
+            type = "binding"
+            vk_code = "0x31"
+            children = [{ type = "key", vk_code = "${key}", up = false, delay = "2f" }]
+        "#;
+
+        let root = read(Path::new("test.toml"), text).unwrap();
+        assert_eq!(root.get_attribute("fps", None), Some("30"));
+
+        let profiles = profiles::parse_sources(&[("test.toml".into(), text.to_string())])
+            .unwrap()
+            .profiles;
+        let action = &profiles[0].bindings[0].actions()[0];
+        assert_eq!(action.to_string(), "key 0x32 down after 66ms");
+    }
+
+    #[test]
+    fn reject_nodes_without_type() {
+        let yaml = |binding: &str| {
+            format!(
+                "profiles:\n  - name: Test\n    bindings:\n      - comment: Jump\n      - {}\n",
+                binding
+            )
+        };
+
+        let root = read(
+            Path::new("test.yaml"),
+            &yaml("{type: binding, vk_code: '0x20'}"),
+        )
+        .unwrap();
+        assert!(to_xml(&root).contains("<!--Jump-->"));
+
+        for binding in &["{vk_code: '0x20'}", "{type: '', vk_code: '0x20'}", "{}"] {
+            let err = read(Path::new("test.yaml"), &yaml(binding)).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Error parsing test.yaml: profiles[0].bindings[1] has no type"
+            );
+        }
+
+        let err = read(
+            Path::new("test.toml"),
+            "[[hotkeys]]\ntype = \"binding\"\nchildren = [{ vk_code = \"0x41\" }]\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error parsing test.toml: hotkeys[0].children[0] has no type"
+        );
+    }
+}
@@ -0,0 +1,830 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::filter::ChatterFilter;
+use crate::metrics::METRICS;
+use crate::profiles::*;
+use crate::throttle::{ThrottleDecision, ThrottleState};
+use crate::windows::{HookAction, InputEvent, KeyboardEvent, MouseButton, MouseEvent, Window};
+
+pub const MACRO_QUEUE_SIZE: usize = 100;
+
+/// How often active profiles are compared with the ones last published.
+pub const ACTIVE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A binding that fired, queued for processing of its actions.
+pub struct MatchedEvent {
+    pub profiles: Arc<Profiles>,
+    pub profile_index: usize,
+    pub binding_index: usize,
+    /// State of the trigger, or None when it has no up/down state (e.g. mouse wheel).
+    pub up: Option<bool>,
+    pub repeat: u32,
+    /// Held back until then, used by trailing-edge throttles.
+    pub deadline: Option<Instant>,
+    /// Blocked input to re-send instead of the binding actions.
+    pub replay: Option<Action>,
+}
+
+/// Changes of the engine state, published to subscribers of the control endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+    Suspended {
+        suspended: bool,
+    },
+    /// A profile was forced active, or None when profiles follow their triggers again.
+    ProfileForced {
+        profile: Option<String>,
+    },
+    /// A profile became active, by its triggers or forced.
+    ProfileActivated {
+        profile: String,
+    },
+    ProfileDeactivated {
+        profile: String,
+    },
+    ProfileEnabled {
+        profile: String,
+        enabled: bool,
+    },
+    BindingFired {
+        profile: String,
+        binding: String,
+    },
+}
+
+/// State shared by the engine and the control actions.
+#[derive(Debug)]
+pub struct EngineState {
+    suspended: AtomicBool,
+    forced_profile: Mutex<Option<String>>,
+    disabled_profiles: Mutex<HashSet<String>>,
+    events: broadcast::Sender<EngineEvent>,
+}
+
+impl Default for EngineState {
+    fn default() -> EngineState {
+        let (events, _) = broadcast::channel(64);
+        EngineState {
+            suspended: AtomicBool::new(false),
+            forced_profile: Mutex::new(None),
+            disabled_profiles: Mutex::new(HashSet::new()),
+            events,
+        }
+    }
+}
+
+impl EngineState {
+    pub fn subscribe(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn publish(&self, event: EngineEvent) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+
+    pub fn forced_profile(&self) -> Option<String> {
+        self.forced_profile.lock().unwrap().clone()
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        !self.disabled_profiles.lock().unwrap().contains(name)
+    }
+
+    pub fn disabled_profiles(&self) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .disabled_profiles
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn set_enabled(&self, name: &str, enabled: bool) {
+        let mut disabled = self.disabled_profiles.lock().unwrap();
+        if enabled {
+            disabled.remove(name);
+            log::info!("Profile \"{}\" enabled", name);
+        } else {
+            disabled.insert(name.to_string());
+            log::info!("Profile \"{}\" disabled", name);
+        }
+        self.publish(EngineEvent::ProfileEnabled {
+            profile: name.to_string(),
+            enabled,
+        });
+    }
+
+    pub fn execute(&self, command: &ControlCommand, profiles: &Profiles) {
+        match command {
+            ControlCommand::Suspend => self.set_suspended(true),
+            ControlCommand::Resume => self.set_suspended(false),
+            ControlCommand::ToggleSuspend => self.set_suspended(!self.is_suspended()),
+            ControlCommand::ActivateProfile(name) => self.set_forced_profile(name.clone()),
+            ControlCommand::CycleProfiles => {
+                let forced = self.forced_profile();
+                let next = match profiles
+                    .profiles
+                    .iter()
+                    .position(|profile| Some(&profile.name) == forced.as_ref())
+                {
+                    Some(index) => profiles.profiles.get(index + 1),
+                    None => profiles.profiles.first(),
+                };
+                self.set_forced_profile(next.map(|profile| profile.name.clone()));
+            }
+        }
+    }
+
+    fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::SeqCst);
+        if suspended {
+            log::info!("Keymapper suspended");
+        } else {
+            log::info!("Keymapper resumed");
+        }
+        self.publish(EngineEvent::Suspended { suspended });
+    }
+
+    fn set_forced_profile(&self, name: Option<String>) {
+        match &name {
+            Some(name) => log::info!("Profile \"{}\" activated", name),
+            None => log::info!("Profiles follow their triggers"),
+        }
+        *self.forced_profile.lock().unwrap() = name.clone();
+        self.publish(EngineEvent::ProfileForced { profile: name });
+    }
+}
+
+/// Publishes profiles activated by their triggers while there is no input to notice it.
+pub async fn watch_active_profiles(engine: Arc<Mutex<Engine>>) {
+    let mut interval = tokio::time::interval(ACTIVE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        engine.lock().unwrap().check_active_profiles(Instant::now());
+    }
+}
+
+/// Matched events held back by trailing-edge throttles, in the order they become due. They
+/// wait outside the scheduler so that other macros don't abort them.
+#[derive(Default)]
+pub struct Deferred {
+    events: VecDeque<MatchedEvent>,
+}
+
+impl Deferred {
+    pub fn push(&mut self, event: MatchedEvent) {
+        let index = self
+            .events
+            .iter()
+            .position(|e| e.deadline > event.deadline)
+            .unwrap_or(self.events.len());
+        self.events.insert(index, event);
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.events.front().and_then(|e| e.deadline)
+    }
+
+    /// The next event due by `now`.
+    pub fn pop(&mut self, now: Instant) -> Option<MatchedEvent> {
+        match self.next_deadline() {
+            Some(deadline) if deadline > now => None,
+            _ => self.events.pop_front(),
+        }
+    }
+}
+
+/// Looks up windows for profile triggers, replaced when replaying recorded input.
+pub trait Environment: Send {
+    fn is_foreground(&self, window_name: &str) -> bool;
+}
+
+pub struct Desktop;
+
+impl Environment for Desktop {
+    fn is_foreground(&self, window_name: &str) -> bool {
+        Window::find(window_name).iter().any(|w| w.is_foreground())
+    }
+}
+
+/// Matches input events against the bindings of active profiles.
+pub struct Engine {
+    profiles: Arc<Profiles>,
+    state: Arc<EngineState>,
+    environment: Box<dyn Environment>,
+    states: Vec<Vec<BindingState>>,
+    filter: ChatterFilter,
+    tx: mpsc::Sender<MatchedEvent>,
+    /// Active profiles as last published, and when they were checked.
+    active: Vec<String>,
+    active_checked: Option<Instant>,
+    /// Whether to count into the metrics, which replays on a virtual clock don't.
+    metrics: bool,
+}
+
+#[derive(Default)]
+struct BindingState {
+    throttle: ThrottleState,
+    wheel_remainder: f32,
+}
+
+impl Engine {
+    pub fn new(
+        profiles: Arc<Profiles>,
+        state: Arc<EngineState>,
+        filter: ChatterFilter,
+        tx: mpsc::Sender<MatchedEvent>,
+    ) -> Engine {
+        let states = binding_states(&profiles);
+
+        Engine {
+            profiles,
+            state,
+            environment: Box::new(Desktop),
+            states,
+            filter,
+            tx,
+            active: Vec::new(),
+            active_checked: None,
+            metrics: true,
+        }
+    }
+
+    pub fn with_environment<E: Environment + 'static>(mut self, environment: E) -> Engine {
+        self.environment = Box::new(environment);
+        self
+    }
+
+    pub fn without_metrics(mut self) -> Engine {
+        self.metrics = false;
+        self
+    }
+
+    pub fn profiles(&self) -> &Arc<Profiles> {
+        &self.profiles
+    }
+
+    /// Replaces the profiles, resetting per-binding state.
+    pub fn reload(&mut self, profiles: Arc<Profiles>) {
+        self.states = binding_states(&profiles);
+        self.profiles = profiles;
+    }
+
+    /// Names of the profiles currently matching input, hotkeys excluded.
+    pub fn active_profiles(&self) -> Vec<String> {
+        self.profiles
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(index, profile)| self.is_active(*index, profile))
+            .map(|(_, profile)| profile.name.clone())
+            .collect()
+    }
+
+    /// Number of macros waiting for processing.
+    pub fn queue_depth(&self) -> usize {
+        MACRO_QUEUE_SIZE - self.tx.capacity()
+    }
+
+    /// Publishes the profiles that became active or inactive since the last check.
+    pub fn check_active_profiles(&mut self, now: Instant) {
+        self.active_checked = Some(now);
+        let active = self.active_profiles();
+        for profile in self.active.iter().filter(|name| !active.contains(name)) {
+            self.state.publish(EngineEvent::ProfileDeactivated {
+                profile: profile.clone(),
+            });
+        }
+        for profile in active.iter().filter(|name| !self.active.contains(name)) {
+            log::debug!("Profile \"{}\" is active", profile);
+            self.state.publish(EngineEvent::ProfileActivated {
+                profile: profile.clone(),
+            });
+        }
+        self.active = active;
+    }
+
+    pub fn handle(&mut self, e: &InputEvent, now: Instant) -> HookAction {
+        let due = self
+            .active_checked
+            .is_none_or(|checked| now.saturating_duration_since(checked) >= ACTIVE_CHECK_INTERVAL);
+        if due {
+            self.check_active_profiles(now);
+        }
+
+        let action = self.match_event(e, now);
+        if self.metrics {
+            METRICS.hook_handled(action == HookAction::Block, now.elapsed());
+        }
+        action
+    }
+
+    fn match_event(&mut self, e: &InputEvent, now: Instant) -> HookAction {
+        match e {
+            InputEvent::Keyboard(e) if e.syntetic() => HookAction::Forward,
+            InputEvent::Mouse(e) if e.syntetic() => HookAction::Forward,
+            InputEvent::Keyboard(e) => {
+                let suspended = self.state.is_suspended();
+                if !suspended && !self.filter.accept(e.vk_code, e.up(), now) {
+                    return HookAction::Block;
+                }
+                self.handle_key(e, now)
+            }
+            InputEvent::Mouse(MouseEvent::Button { button, up, .. }) => {
+                self.handle_mouse_button(*button, *up, now)
+            }
+            InputEvent::Mouse(MouseEvent::MouseWheel { delta, .. }) => {
+                self.handle_mouse_wheel(false, *delta, now)
+            }
+            InputEvent::Mouse(MouseEvent::MouseHWheel { delta, .. }) => {
+                self.handle_mouse_wheel(true, *delta, now)
+            }
+        }
+    }
+
+    fn handle_key(&mut self, e: &KeyboardEvent, now: Instant) -> HookAction {
+        let profiles = self.profiles.clone();
+
+        for (profile_index, profile) in profiles.iter().enumerate() {
+            for (binding_index, binding) in profile.bindings.iter().enumerate() {
+                if let Binding::Key(binding) = binding {
+                    if is_key_match(binding, e) && self.is_active(profile_index, profile) {
+                        log::trace!(
+                            "Profile \"{}\" blocked key: {:X} + {:X}",
+                            profile.name,
+                            e.vk_code,
+                            e.flags
+                        );
+
+                        self.queue_actions(profile_index, binding_index, Some(e.up()), 1, now);
+                        return HookAction::Block;
+                    }
+                }
+            }
+        }
+
+        HookAction::Forward
+    }
+
+    fn handle_mouse_button(&mut self, button: MouseButton, up: bool, now: Instant) -> HookAction {
+        let profiles = self.profiles.clone();
+
+        for (profile_index, profile) in profiles.iter().enumerate() {
+            for (binding_index, binding) in profile.bindings.iter().enumerate() {
+                if let Binding::MouseButton(binding) = binding {
+                    if is_mouse_button_match(binding, button, up)
+                        && self.is_active(profile_index, profile)
+                    {
+                        log::trace!(
+                            "Profile \"{}\" blocked mouse button: {:?}, up = {:?}",
+                            profile.name,
+                            button,
+                            up
+                        );
+
+                        self.queue_actions(profile_index, binding_index, Some(up), 1, now);
+                        return HookAction::Block;
+                    }
+                }
+            }
+        }
+
+        HookAction::Forward
+    }
+
+    fn handle_mouse_wheel(&mut self, horizontal: bool, delta: i16, now: Instant) -> HookAction {
+        let profiles = self.profiles.clone();
+        let up = delta > 0;
+
+        for (profile_index, profile) in profiles.iter().enumerate() {
+            for (binding_index, binding) in profile.bindings.iter().enumerate() {
+                if let Binding::MouseWheel(binding) = binding {
+                    let matched =
+                        binding.horizontal == horizontal && binding.up.iter().all(|v| *v == up);
+
+                    if !matched || !self.is_active(profile_index, profile) {
+                        continue;
+                    }
+
+                    if !binding.actions.is_empty() {
+                        let state = &mut self.states[profile_index][binding_index];
+                        let repeat = binding.accumulate(&mut state.wheel_remainder, delta);
+                        if repeat > 0 {
+                            self.queue_actions(profile_index, binding_index, None, repeat, now);
+                        }
+                        return HookAction::Block;
+                    }
+
+                    match self.check_throttle(profile_index, binding_index, now) {
+                        ThrottleDecision::Pass => {}
+                        ThrottleDecision::Drop => {
+                            log::trace!(
+                                "Profile \"{}\" throttle mouse wheel (up={})",
+                                profile.name,
+                                up
+                            );
+                            return HookAction::Block;
+                        }
+                        ThrottleDecision::Defer(delay) => {
+                            let scroll = Scroll {
+                                delta: delta as i32,
+                                horizontal,
+                                delay: None,
+                            };
+                            self.queue(MatchedEvent {
+                                profiles: profiles.clone(),
+                                profile_index,
+                                binding_index,
+                                up: None,
+                                repeat: 1,
+                                deadline: Some(now + delay),
+                                replay: Some(Action::Scroll(scroll)),
+                            });
+                            return HookAction::Block;
+                        }
+                    }
+                }
+            }
+        }
+
+        HookAction::Forward
+    }
+
+    /// Queues the binding actions unless its throttle drops them. Releases pass when their
+    /// press did, after it if the throttle holds it back.
+    fn queue_actions(
+        &mut self,
+        profile_index: usize,
+        binding_index: usize,
+        up: Option<bool>,
+        repeat: u32,
+        now: Instant,
+    ) {
+        let profiles = self.profiles.clone();
+        let profile = &profiles[profile_index];
+        if profile.bindings[binding_index].actions().is_empty() {
+            return;
+        }
+
+        let decision = if up == Some(true) {
+            self.check_release_throttle(profile_index, binding_index, now)
+        } else {
+            self.check_throttle(profile_index, binding_index, now)
+        };
+        let delay = match decision {
+            ThrottleDecision::Pass => None,
+            ThrottleDecision::Defer(delay) => Some(delay),
+            ThrottleDecision::Drop => {
+                log::trace!("Profile \"{}\" throttle binding actions", profile.name);
+                return;
+            }
+        };
+
+        // suspending can't wait behind other macros or be dropped with a full queue
+        if up != Some(true) {
+            for action in profile.bindings[binding_index].actions() {
+                match action {
+                    Action::Control(control) if control.command.is_suspend() => {
+                        self.state.execute(&control.command, &profiles)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.queue(MatchedEvent {
+            profiles: profiles.clone(),
+            profile_index,
+            binding_index,
+            up,
+            repeat,
+            deadline: delay.map(|delay| now + delay),
+            replay: None,
+        });
+    }
+
+    fn check_throttle(
+        &mut self,
+        profile_index: usize,
+        binding_index: usize,
+        now: Instant,
+    ) -> ThrottleDecision {
+        match self.profiles[profile_index].bindings[binding_index].throttle() {
+            Some(throttle) => self.states[profile_index][binding_index]
+                .throttle
+                .check(&throttle, now),
+            None => ThrottleDecision::Pass,
+        }
+    }
+
+    fn check_release_throttle(
+        &mut self,
+        profile_index: usize,
+        binding_index: usize,
+        now: Instant,
+    ) -> ThrottleDecision {
+        match self.profiles[profile_index].bindings[binding_index].throttle() {
+            Some(_) => self.states[profile_index][binding_index]
+                .throttle
+                .check_release(now),
+            None => ThrottleDecision::Pass,
+        }
+    }
+
+    fn is_active(&self, profile_index: usize, profile: &Profile) -> bool {
+        // hotkeys are always active
+        if profile_index == 0 {
+            return true;
+        }
+
+        if self.state.is_suspended() || !self.state.is_enabled(&profile.name) {
+            return false;
+        }
+
+        self.state.forced_profile().as_ref() == Some(&profile.name) || self.is_triggered(profile)
+    }
+
+    fn is_triggered(&self, profile: &Profile) -> bool {
+        profile.triggers.iter().any(|trigger| match trigger {
+            Trigger::Window { name } => self.environment.is_foreground(name),
+        })
+    }
+
+    fn queue(&self, event: MatchedEvent) {
+        let profile = &event.profiles[event.profile_index];
+        let binding = profile.bindings[event.binding_index].to_string();
+        if self.metrics {
+            METRICS.binding_fired(&profile.name, &binding);
+        }
+        self.state.publish(EngineEvent::BindingFired {
+            profile: profile.name.clone(),
+            binding,
+        });
+
+        if self.tx.try_send(event).is_err() {
+            if self.metrics {
+                METRICS.queue_dropped();
+            }
+            log::error!("Failed to add macro to processing queue.");
+        }
+    }
+}
+
+fn binding_states(profiles: &Profiles) -> Vec<Vec<BindingState>> {
+    profiles
+        .iter()
+        .map(|profile| {
+            profile
+                .bindings
+                .iter()
+                .map(|_| BindingState::default())
+                .collect()
+        })
+        .collect()
+}
+
+fn is_key_match(binding: &KeyBinding, e: &KeyboardEvent) -> bool {
+    let vcode_matched = binding.vk_code == e.vk_code;
+    let up_matched = binding.up.into_iter().all(|v| v == e.up());
+    let alt_matched = binding.alt.into_iter().all(|v| v == e.alt());
+    vcode_matched && up_matched && alt_matched
+}
+
+fn is_mouse_button_match(binding: &MouseButtonBinding, button: MouseButton, up: bool) -> bool {
+    let button_matched = binding.button == button;
+    let up_matched = binding.up.into_iter().all(|v| v == up);
+    button_matched && up_matched
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn cycle_profiles() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="A"/>
+                <profile name="B"/>
+            </profiles>"#,
+        )
+        .unwrap();
+        let state = EngineState::default();
+
+        let cycle = || {
+            state.execute(&ControlCommand::CycleProfiles, &profiles);
+            state.forced_profile()
+        };
+
+        assert_eq!(cycle().as_deref(), Some("A"));
+        assert_eq!(cycle().as_deref(), Some("B"));
+        assert_eq!(cycle().as_deref(), None);
+        assert_eq!(cycle().as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn toggle_suspend() {
+        let profiles = parse_profiles("<profiles/>").unwrap();
+        let state = EngineState::default();
+
+        state.execute(&ControlCommand::ToggleSuspend, &profiles);
+        assert!(state.is_suspended());
+        state.execute(&ControlCommand::ToggleSuspend, &profiles);
+        assert!(!state.is_suspended());
+    }
+
+    /// Outputs of the key presses and releases, with held back macros run in order at the end.
+    fn throttled_outputs(throttle_mode: &str, keys: &[(u64, bool)]) -> Vec<(u32, bool)> {
+        let profiles = parse_profiles(&format!(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x31" throttle="100" throttle_mode="{}">
+                            <key vk_code="0x32"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+            throttle_mode
+        ))
+        .unwrap();
+        let state = Arc::new(EngineState::default());
+        state.execute(
+            &ControlCommand::ActivateProfile(Some("Test".to_string())),
+            &profiles,
+        );
+        let (tx, mut rx) = mpsc::channel(MACRO_QUEUE_SIZE);
+        let filter = ChatterFilter::new(&Default::default()).unwrap();
+        let mut engine = Engine::new(Arc::new(profiles), state, filter, tx);
+
+        let start = Instant::now();
+        let mut deferred = Deferred::default();
+        let mut outputs = vec![];
+        let mut rng = StdRng::seed_from_u64(0);
+        for (ms, up) in keys {
+            let e = InputEvent::Keyboard(KeyboardEvent::new(0x31, *up));
+            engine.handle(&e, start + Duration::from_millis(*ms));
+            while let Ok(event) = rx.try_recv() {
+                match event.deadline {
+                    Some(_) => deferred.push(event),
+                    None => outputs.extend(crate::executor::steps(&event, &mut rng)),
+                }
+            }
+        }
+        while let Some(deadline) = deferred.next_deadline() {
+            let event = deferred.pop(deadline).unwrap();
+            outputs.extend(crate::executor::steps(&event, &mut rng));
+        }
+
+        outputs
+            .into_iter()
+            .map(|step| match step.output {
+                crate::executor::Output::Key { vk_code, up } => (vk_code, up),
+                output => panic!("unexpected output {:?}", output),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn throttle_passes_releases() {
+        // the press at 20 is dropped and so is its release
+        let keys = [
+            (0, false),
+            (10, true),
+            (20, false),
+            (30, true),
+            (150, false),
+            (160, true),
+        ];
+        for mode in &["leading", "trailing", "sliding"] {
+            assert_eq!(
+                throttled_outputs(mode, &keys),
+                vec![(0x32, false), (0x32, true), (0x32, false), (0x32, true)],
+                "{}",
+                mode
+            );
+        }
+        assert_eq!(
+            throttled_outputs("trailing", &keys[..3]),
+            vec![(0x32, false), (0x32, true)]
+        );
+    }
+
+    #[test]
+    fn suspend_as_hotkey_matches() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <hotkeys>
+                    <binding vk_code="0x13" up="false"><toggle-suspend/></binding>
+                </hotkeys>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x41"><key vk_code="0x42"/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap();
+        let state = Arc::new(EngineState::default());
+        state.execute(
+            &ControlCommand::ActivateProfile(Some("Test".to_string())),
+            &profiles,
+        );
+        // a full queue doesn't stop the hotkey
+        let (tx, _rx) = mpsc::channel(1);
+        let filter = ChatterFilter::new(&Default::default()).unwrap();
+        let mut engine = Engine::new(Arc::new(profiles), state.clone(), filter, tx);
+        let key = |vk_code, up| InputEvent::Keyboard(KeyboardEvent::new(vk_code, up));
+        let now = Instant::now();
+
+        assert_eq!(engine.handle(&key(0x41, false), now), HookAction::Block);
+        assert_eq!(engine.handle(&key(0x13, false), now), HookAction::Block);
+        assert!(state.is_suspended());
+        assert_eq!(engine.handle(&key(0x41, false), now), HookAction::Forward);
+        engine.handle(&key(0x13, true), now);
+        engine.handle(&key(0x13, false), now);
+        assert!(!state.is_suspended());
+    }
+
+    struct Foreground(Arc<Mutex<String>>);
+
+    impl Environment for Foreground {
+        fn is_foreground(&self, window_name: &str) -> bool {
+            *self.0.lock().unwrap() == window_name
+        }
+    }
+
+    #[test]
+    fn publish_triggered_profiles() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Game">
+                    <triggers><window name="Game"/></triggers>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap();
+        let state = Arc::new(EngineState::default());
+        let mut events = state.subscribe();
+        let (tx, _rx) = mpsc::channel(MACRO_QUEUE_SIZE);
+        let filter = ChatterFilter::new(&Default::default()).unwrap();
+        let window = Arc::new(Mutex::new("Game".to_string()));
+        let mut engine = Engine::new(Arc::new(profiles), state, filter, tx)
+            .with_environment(Foreground(window.clone()));
+        let key = InputEvent::Keyboard(KeyboardEvent::new(0x41, false));
+        let start = Instant::now();
+
+        engine.handle(&key, start);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(EngineEvent::ProfileActivated { profile }) if profile == "Game"
+        ));
+
+        *window.lock().unwrap() = "Notepad".to_string();
+        engine.handle(&key, start + Duration::from_millis(10));
+        assert!(events.try_recv().is_err());
+        engine.check_active_profiles(start + Duration::from_millis(20));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(EngineEvent::ProfileDeactivated { profile }) if profile == "Game"
+        ));
+    }
+
+    #[test]
+    fn publish_state_changes() {
+        let profiles = parse_profiles(r#"<profiles><profile name="A"/></profiles>"#).unwrap();
+        let state = EngineState::default();
+        let mut events = state.subscribe();
+
+        state.execute(&ControlCommand::Suspend, &profiles);
+        state.execute(&ControlCommand::CycleProfiles, &profiles);
+
+        assert!(matches!(
+            events.try_recv(),
+            Ok(EngineEvent::Suspended { suspended: true })
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(EngineEvent::ProfileForced { profile: Some(name) }) if name == "A"
+        ));
+        assert!(events.try_recv().is_err());
+    }
+}
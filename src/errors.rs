@@ -32,6 +32,7 @@ impl fmt::Display for AppError {
 }
 
 impl Error for AppError {
+    #[allow(deprecated)]
     fn description(&self) -> &str {
         match *self {
             AppError::Custom { ref description } => description,
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::Serialize;
+
+use crate::engine::MatchedEvent;
+use crate::profiles::*;
+use crate::windows::MouseButton;
+
+/// A single output of a macro.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "output", rename_all = "snake_case")]
+pub enum Output {
+    Key { vk_code: u32, up: bool },
+    Button { button: MouseButton, up: bool },
+    Move { x: i32, y: i32, absolute: bool },
+    Scroll { delta: i32, horizontal: bool },
+    Run(Run),
+    Control { command: ControlCommand },
+}
+
+/// An output sent once `delay` has passed after the previous step.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub delay: Duration,
+    pub output: Output,
+}
+
+/// Expands a fired binding into the outputs of its macro, drawing delay jitter from `rng`.
+pub fn steps<R: Rng>(e: &MatchedEvent, rng: &mut R) -> Vec<Step> {
+    let binding = match e
+        .profiles
+        .get(e.profile_index)
+        .and_then(|profile| profile.bindings.get(e.binding_index))
+    {
+        Some(binding) => binding,
+        None => return vec![],
+    };
+
+    let mut steps = Vec::new();
+    let mut delay = Duration::default();
+
+    if let Some(action) = &e.replay {
+        push_action(&mut steps, &mut delay, action, e.up);
+        return steps;
+    }
+
+    for _ in 0..e.repeat {
+        for action in binding.actions() {
+            if let Some(action_delay) = action.delay() {
+                delay += match action.jitter().or_else(|| binding.jitter()) {
+                    Some(jitter) => apply_jitter(action_delay, &jitter, rng),
+                    None => action_delay,
+                };
+            }
+            push_action(&mut steps, &mut delay, action, e.up);
+        }
+    }
+
+    steps
+}
+
+fn apply_jitter<R: Rng>(delay: Duration, jitter: &Jitter, rng: &mut R) -> Duration {
+    let range = jitter.range.as_secs_f64();
+    if range == 0.0 {
+        return delay;
+    }
+
+    let offset = match jitter.mode {
+        JitterMode::Uniform => rng.random_range(-range..=range),
+        JitterMode::Gaussian => {
+            // Box-Muller transform
+            let u1: f64 = 1.0 - rng.random::<f64>();
+            let u2: f64 = rng.random();
+            let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            (z * range / 2.0).max(-range).min(range)
+        }
+    };
+
+    Duration::from_secs_f64((delay.as_secs_f64() + offset).max(0.0))
+}
+
+fn push_action(
+    steps: &mut Vec<Step>,
+    delay: &mut Duration,
+    action: &Action,
+    trigger_up: Option<bool>,
+) {
+    let mut push = |output| {
+        steps.push(Step {
+            delay: *delay,
+            output,
+        });
+        *delay = Duration::default();
+    };
+
+    match action {
+        Action::Key(key) => {
+            for up in press_states(key.up.or(trigger_up)) {
+                push(Output::Key {
+                    vk_code: key.vk_code,
+                    up,
+                });
+            }
+        }
+        Action::Button(button) => {
+            for up in press_states(button.up.or(trigger_up)) {
+                push(Output::Button {
+                    button: button.button,
+                    up,
+                });
+            }
+        }
+        Action::Move(movement) => push(Output::Move {
+            x: movement.x,
+            y: movement.y,
+            absolute: movement.absolute,
+        }),
+        Action::Scroll(scroll) => push(Output::Scroll {
+            delta: scroll.delta,
+            horizontal: scroll.horizontal,
+        }),
+        Action::Run(run) => push(Output::Run(run.clone())),
+        Action::Control(control) => {
+            // the engine applied suspend commands already
+            if trigger_up != Some(true) && !control.command.is_suspend() {
+                push(Output::Control {
+                    command: control.command.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Press or release, or a full press and release when the state is unknown.
+fn press_states(up: Option<bool>) -> Vec<bool> {
+    match up {
+        Some(up) => vec![up],
+        None => vec![false, true],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn delays(e: &MatchedEvent, seed: u64) -> Vec<Duration> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        steps(e, &mut rng).iter().map(|step| step.delay).collect()
+    }
+
+    #[test]
+    fn jitter_delays_within_range() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x31" up="false" jitter="10">
+                            <key vk_code="0x41" delay="50"/>
+                            <key vk_code="0x42" delay="50" jitter="20" jitter_mode="gaussian"/>
+                            <key vk_code="0x43"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap();
+        let e = MatchedEvent {
+            profiles: Arc::new(profiles),
+            profile_index: 1,
+            binding_index: 0,
+            up: Some(false),
+            repeat: 100,
+            deadline: None,
+            replay: None,
+        };
+
+        let first = delays(&e, 7);
+        assert_eq!(first, delays(&e, 7));
+        assert_ne!(first, delays(&e, 8));
+
+        let ms = |ms| Duration::from_millis(ms);
+        for step in first.chunks(3) {
+            assert!(step[0] >= ms(40) && step[0] <= ms(60));
+            assert!(step[1] >= ms(30) && step[1] <= ms(70));
+            assert_eq!(step[2], Duration::default());
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::errors::AppError;
+use crate::settings::DebounceSettings;
+
+/// How long after a dropped press the statistics are logged, with the presses dropped meanwhile.
+const STATISTICS_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Drops key presses that bounce off a release of the same key, as worn switches do.
+/// Statistics of dropped presses are logged every `STATISTICS_INTERVAL` while presses are
+/// dropped, and when the filter is dropped.
+pub struct ChatterFilter {
+    window: Duration,
+    key_windows: HashMap<u32, Duration>,
+    released: HashMap<u32, Instant>,
+    suppressed: HashSet<u32>,
+    dropped: HashMap<u32, u64>,
+    /// When to log the statistics, set by a dropped press.
+    statistics_due: Option<Instant>,
+}
+
+impl ChatterFilter {
+    pub fn new(settings: &DebounceSettings) -> Result<ChatterFilter, AppError> {
+        let key_windows = settings
+            .keys
+            .iter()
+            .map(|(vk_code, window)| {
+                let text = vk_code.trim_start_matches("0x");
+                let vk_code = u32::from_str_radix(text, 16)
+                    .map_err(|_| AppError::new(format!("Invalid debounce key code {}", vk_code)))?;
+                Ok((vk_code, Duration::from_millis(*window)))
+            })
+            .collect::<Result<_, AppError>>()?;
+
+        Ok(ChatterFilter {
+            window: Duration::from_millis(settings.window),
+            key_windows,
+            released: HashMap::new(),
+            suppressed: HashSet::new(),
+            dropped: HashMap::new(),
+            statistics_due: None,
+        })
+    }
+
+    /// Returns false if the key event is chatter and should be dropped.
+    pub fn accept(&mut self, vk_code: u32, up: bool, now: Instant) -> bool {
+        if self.statistics_due.is_some_and(|due| now >= due) {
+            self.statistics_due = None;
+            self.log_statistics();
+        }
+
+        let window = self
+            .key_windows
+            .get(&vk_code)
+            .copied()
+            .unwrap_or(self.window);
+        if window == Duration::from_millis(0) {
+            return true;
+        }
+
+        if up {
+            // the release that ends a dropped press is dropped too
+            if self.suppressed.remove(&vk_code) {
+                return false;
+            }
+            self.released.insert(vk_code, now);
+            return true;
+        }
+
+        if self.suppressed.contains(&vk_code) {
+            return false;
+        }
+
+        match self.released.get(&vk_code) {
+            Some(&released) if now.duration_since(released) < window => {
+                self.suppressed.insert(vk_code);
+                let count = self.dropped.entry(vk_code).or_insert(0);
+                *count += 1;
+                log::info!(
+                    "Dropped chatter on key {:X} {:?} after release ({} so far)",
+                    vk_code,
+                    now.duration_since(released),
+                    count
+                );
+                self.statistics_due.get_or_insert(now + STATISTICS_INTERVAL);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub fn log_statistics(&self) {
+        let mut dropped: Vec<_> = self.dropped.iter().collect();
+        dropped.sort_by(|a, b| b.1.cmp(a.1));
+
+        for (vk_code, count) in dropped {
+            log::info!("Chatter on key {:X}: {} presses dropped", vk_code, count);
+        }
+    }
+}
+
+impl Drop for ChatterFilter {
+    fn drop(&mut self) {
+        self.log_statistics();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(window: u64, keys: &[(&str, u64)]) -> ChatterFilter {
+        let settings = DebounceSettings {
+            window,
+            keys: keys.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+        };
+        ChatterFilter::new(&settings).unwrap()
+    }
+
+    #[test]
+    fn drops_bounced_press_and_its_release() {
+        let mut filter = filter(30, &[]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(filter.accept(0x41, false, at(0)));
+        assert!(filter.accept(0x41, true, at(80)));
+        assert!(!filter.accept(0x41, false, at(90)));
+        assert!(!filter.accept(0x41, true, at(95)));
+        assert!(filter.accept(0x41, false, at(200)));
+        assert_eq!(filter.dropped[&0x41], 1);
+    }
+
+    #[test]
+    fn log_statistics_after_dropped_presses() {
+        let mut filter = filter(30, &[]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(filter.accept(0x41, true, at(0)));
+        assert_eq!(filter.statistics_due, None);
+        assert!(!filter.accept(0x41, false, at(10)));
+        assert!(!filter.accept(0x41, true, at(20)));
+        assert!(!filter.accept(0x41, false, at(25)));
+        assert_eq!(filter.statistics_due, Some(at(10) + STATISTICS_INTERVAL));
+
+        assert!(filter.accept(0x42, false, at(10) + STATISTICS_INTERVAL));
+        assert_eq!(filter.statistics_due, None);
+    }
+
+    #[test]
+    fn keeps_auto_repeat_and_other_keys() {
+        let mut filter = filter(30, &[]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(filter.accept(0x41, false, at(0)));
+        assert!(filter.accept(0x41, false, at(10)));
+        assert!(filter.accept(0x41, true, at(20)));
+        assert!(filter.accept(0x42, false, at(25)));
+    }
+
+    #[test]
+    fn uses_per_key_windows() {
+        let mut filter = filter(0, &[("0x41", 50)]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(filter.accept(0x42, true, at(0)));
+        assert!(filter.accept(0x42, false, at(5)));
+        assert!(filter.accept(0x41, true, at(0)));
+        assert!(!filter.accept(0x41, false, at(40)));
+    }
+}
@@ -0,0 +1,129 @@
+//! Names of virtual keys, as used in macro strings and key code attributes.
+
+/// Looks up a key by name, case-insensitively. Letters, digits, `F1`-`F24`, `Num0`-`Num9`
+/// and hex codes like `0x41` are recognised besides the names below.
+pub fn vk_code(name: &str) -> Option<u32> {
+    let name = name.to_ascii_lowercase();
+
+    if let Some(hex) = name.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_alphanumeric() {
+            return Some(c.to_ascii_uppercase() as u32);
+        }
+    }
+
+    let numbered = |prefix: &str, first: u32, count: u32| {
+        name.strip_prefix(prefix)
+            .and_then(|n| n.parse::<u32>().ok())
+            .filter(|n| *n >= first && *n < first + count)
+            .map(|n| n - first)
+    };
+    if let Some(n) = numbered("f", 1, 24) {
+        return Some(0x70 + n);
+    }
+    if let Some(n) = numbered("num", 0, 10) {
+        return Some(0x60 + n);
+    }
+
+    NAMES
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, vk_code)| *vk_code)
+}
+
+/// Key names for completion, one spelling of each besides the table's aliases.
+pub fn names() -> Vec<String> {
+    let letters = (b'A'..=b'Z').map(|c| (c as char).to_string());
+    let digits = (0..10).map(|n| n.to_string());
+    let functions = (1..=24).map(|n| format!("F{}", n));
+    let numpad = (0..10).map(|n| format!("Num{}", n));
+    let named = NAMES.iter().map(|(name, _)| name.to_string());
+
+    letters
+        .chain(digits)
+        .chain(functions)
+        .chain(numpad)
+        .chain(named)
+        .collect()
+}
+
+const NAMES: &[(&str, u32)] = &[
+    ("backspace", 0x08),
+    ("tab", 0x09),
+    ("enter", 0x0D),
+    ("return", 0x0D),
+    ("shift", 0x10),
+    ("ctrl", 0x11),
+    ("control", 0x11),
+    ("alt", 0x12),
+    ("pause", 0x13),
+    ("capslock", 0x14),
+    ("esc", 0x1B),
+    ("escape", 0x1B),
+    ("space", 0x20),
+    ("pageup", 0x21),
+    ("pgup", 0x21),
+    ("pagedown", 0x22),
+    ("pgdn", 0x22),
+    ("end", 0x23),
+    ("home", 0x24),
+    ("left", 0x25),
+    ("up", 0x26),
+    ("right", 0x27),
+    ("down", 0x28),
+    ("printscreen", 0x2C),
+    ("insert", 0x2D),
+    ("ins", 0x2D),
+    ("delete", 0x2E),
+    ("del", 0x2E),
+    ("win", 0x5B),
+    ("lwin", 0x5B),
+    ("rwin", 0x5C),
+    ("apps", 0x5D),
+    ("multiply", 0x6A),
+    ("add", 0x6B),
+    ("subtract", 0x6D),
+    ("decimal", 0x6E),
+    ("divide", 0x6F),
+    ("numlock", 0x90),
+    ("scrolllock", 0x91),
+    ("lshift", 0xA0),
+    ("rshift", 0xA1),
+    ("lctrl", 0xA2),
+    ("rctrl", 0xA3),
+    ("lalt", 0xA4),
+    ("ralt", 0xA5),
+    ("semicolon", 0xBA),
+    ("equals", 0xBB),
+    ("comma", 0xBC),
+    ("minus", 0xBD),
+    ("period", 0xBE),
+    ("slash", 0xBF),
+    ("backquote", 0xC0),
+    ("lbracket", 0xDB),
+    ("backslash", 0xDC),
+    ("rbracket", 0xDD),
+    ("quote", 0xDE),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_up_key_names() {
+        assert_eq!(vk_code("Ctrl"), Some(0x11));
+        assert_eq!(vk_code("c"), Some(0x43));
+        assert_eq!(vk_code("7"), Some(0x37));
+        assert_eq!(vk_code("F12"), Some(0x7B));
+        assert_eq!(vk_code("Num5"), Some(0x65));
+        assert_eq!(vk_code("0xBA"), Some(0xBA));
+        assert_eq!(vk_code("F25"), None);
+        assert_eq!(vk_code("Ctlr"), None);
+        assert!(names().iter().all(|name| vk_code(name).is_some()));
+    }
+}
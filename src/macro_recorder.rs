@@ -0,0 +1,279 @@
+//! `keymapper record-macro`: captures key presses with their timings and prints them as
+//! `<key>` elements, or inserts them into a named binding of the profile files.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use xml::{Element, Xml};
+
+use crate::document::{self, Format};
+use crate::errors::AppError;
+use crate::profiles;
+use crate::windows::{self, Hook, HookAction};
+
+const VK_ESCAPE: u32 = 0x1B;
+
+const USAGE: &str = "Usage: keymapper record-macro [--quantize <ms>] [--insert <binding name>]";
+
+/// A recorded key event, `delay` after the previous one.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct RecordedKey {
+    pub vk_code: u32,
+    pub up: bool,
+    pub delay: Duration,
+}
+
+/// Runs `keymapper record-macro` and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    match record_macro(args) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn record_macro(args: &[String]) -> Result<(), AppError> {
+    let mut quantize = None;
+    let mut insert = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--quantize", Some(ms)) => {
+                let ms = ms
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|ms| *ms > 0)
+                    .ok_or_else(|| AppError::new(format!("Invalid quantize step {}", ms)))?;
+                quantize = Some(Duration::from_millis(ms));
+            }
+            ("--insert", Some(name)) => insert = Some(name.clone()),
+            _ => return Err(AppError::new(USAGE)),
+        }
+    }
+
+    println!("Recording keys, press Escape to stop..");
+    let keys = capture_keys();
+    let keys = match quantize {
+        Some(step) => quantize_delays(&keys, step),
+        None => keys,
+    };
+    let elements = key_elements(&keys);
+
+    match insert {
+        Some(name) => {
+            let mut sources = Vec::new();
+            for path in profiles::profile_files()? {
+                let text = fs::read_to_string(&path)?;
+                sources.push((path, text));
+            }
+
+            let mut found = None;
+            for (index, (path, text)) in sources.iter().enumerate() {
+                let mut root = document::read(path, text)?;
+                if insert_into_binding(&mut root, &name, &elements) {
+                    found = Some((index, root));
+                    break;
+                }
+            }
+            let (index, root) =
+                found.ok_or_else(|| AppError::new(format!("Binding \"{}\" not found", name)))?;
+            let format = Format::from_path(&sources[index].0).ok_or_else(|| {
+                AppError::new(format!(
+                    "Unknown profile format: {}",
+                    sources[index].0.display()
+                ))
+            })?;
+            sources[index].1 = document::write(&root, format)?;
+            // never write a file that would fail to load
+            profiles::parse_sources(&sources)?;
+
+            let (path, text) = &sources[index];
+            fs::write(path, text)?;
+            println!(
+                "Inserted {} keys into binding \"{}\" of {}",
+                elements.len(),
+                name,
+                path.display()
+            );
+        }
+        None => {
+            for element in &elements {
+                print!("{}", document::to_xml(element));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Records key events until Escape is pressed. Auto-repeated presses are skipped.
+fn capture_keys() -> Vec<RecordedKey> {
+    let keys = Rc::new(RefCell::new(Vec::new()));
+    let pressed = RefCell::new(HashSet::new());
+    let last = RefCell::new(None::<Instant>);
+
+    let hook_keys = keys.clone();
+    let _hook = Hook::set_keyboard_hook(move |e| {
+        if e.syntetic() {
+            return HookAction::Forward;
+        }
+        if e.vk_code == VK_ESCAPE {
+            if !e.up() {
+                windows::post_quit_message();
+            }
+            return HookAction::Forward;
+        }
+
+        let repeated = if e.up() {
+            !pressed.borrow_mut().remove(&e.vk_code)
+        } else {
+            !pressed.borrow_mut().insert(e.vk_code)
+        };
+        if repeated {
+            return HookAction::Forward;
+        }
+
+        let now = Instant::now();
+        let delay = match last.replace(Some(now)) {
+            Some(last) => now.duration_since(last),
+            None => Duration::default(),
+        };
+        hook_keys.borrow_mut().push(RecordedKey {
+            vk_code: e.vk_code,
+            up: e.up(),
+            delay,
+        });
+
+        HookAction::Forward
+    });
+
+    windows::message_loop();
+
+    let keys = keys.borrow().clone();
+    keys
+}
+
+/// Rounds each delay to the nearest multiple of `step`.
+pub fn quantize_delays(keys: &[RecordedKey], step: Duration) -> Vec<RecordedKey> {
+    let step = step.as_millis() as u64;
+    keys.iter()
+        .map(|key| {
+            let ms = key.delay.as_millis() as u64;
+            RecordedKey {
+                delay: Duration::from_millis((ms + step / 2) / step * step),
+                ..*key
+            }
+        })
+        .collect()
+}
+
+pub fn key_elements(keys: &[RecordedKey]) -> Vec<Element> {
+    keys.iter()
+        .map(|key| {
+            let mut attributes = vec![
+                (
+                    "vk_code".to_string(),
+                    None,
+                    format!("0x{:02X}", key.vk_code),
+                ),
+                ("up".to_string(), None, key.up.to_string()),
+            ];
+            let delay = key.delay.as_millis();
+            if delay > 0 {
+                attributes.push(("delay".to_string(), None, delay.to_string()));
+            }
+            Element::new("key".to_string(), None, attributes)
+        })
+        .collect()
+}
+
+/// Appends elements to the binding with the given name, returns false if there is none.
+pub fn insert_into_binding(e: &mut Element, name: &str, elements: &[Element]) -> bool {
+    let binding = ["binding", "mouse-button", "mouse-wheel"].contains(&e.name.as_str());
+    if binding && e.get_attribute("name", None) == Some(name) {
+        e.children
+            .extend(elements.iter().cloned().map(Xml::ElementNode));
+        return true;
+    }
+
+    e.children.iter_mut().any(|child| match child {
+        Xml::ElementNode(child) => insert_into_binding(child, name, elements),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(vk_code: u32, up: bool, delay: u64) -> RecordedKey {
+        RecordedKey {
+            vk_code,
+            up,
+            delay: Duration::from_millis(delay),
+        }
+    }
+
+    #[test]
+    fn format_quantized_keys() {
+        let keys = [
+            key(0x53, false, 0),
+            key(0x53, true, 47),
+            key(0x44, false, 74),
+        ];
+        let keys = quantize_delays(&keys, Duration::from_millis(50));
+        let formatted: Vec<_> = key_elements(&keys).iter().map(document::to_xml).collect();
+
+        assert_eq!(
+            formatted,
+            vec![
+                "<key vk_code=\"0x53\" up=\"false\"/>\n",
+                "<key vk_code=\"0x53\" up=\"true\" delay=\"50\"/>\n",
+                "<key vk_code=\"0x44\" up=\"false\" delay=\"50\"/>\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn insert_keys_into_named_binding() {
+        let text = r#"<profiles>
+    <profile name="MK11">
+        <bindings>
+            <binding name="combo" vk_code="0x31">
+                <!-- keep me -->
+                <key vk_code="0x41"/>
+            </binding>
+            <binding name="empty" vk_code="0x32"/>
+        </bindings>
+    </profile>
+</profiles>
+"#;
+        let mut root: Element = text.parse().unwrap();
+        let elements = key_elements(&[key(0x53, false, 0)]);
+
+        assert!(insert_into_binding(&mut root, "combo", &elements));
+        assert!(insert_into_binding(&mut root, "empty", &elements));
+        assert!(!insert_into_binding(&mut root, "MK11", &elements));
+
+        let text = document::to_xml(&root);
+        assert!(text.contains(
+            r#"                <key vk_code="0x41"/>
+                <key vk_code="0x53" up="false"/>
+            </binding>"#
+        ));
+        assert!(text.contains(
+            r#"            <binding name="empty" vk_code="0x32">
+                <key vk_code="0x53" up="false"/>
+            </binding>"#
+        ));
+        assert!(text.contains("<!-- keep me -->"));
+        assert!(profiles::parse_profiles(&text).is_ok());
+    }
+}
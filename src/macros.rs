@@ -0,0 +1,169 @@
+//! Compact macro strings, e.g. `Ctrl+C, 50ms, Ctrl+V`: comma separated chords of key
+//! names joined by `+`, and waits in milliseconds or with a duration unit. Chords press
+//! their keys in order and release them in reverse, a wait replaces the gap before the
+//! next chord. Single digits and hex codes like `0x41` are keys, not waits.
+
+use std::time::Duration;
+
+use crate::errors::AppError;
+use crate::keys;
+use crate::profiles::{parse_duration, Key};
+
+const DEFAULT_TIMING: Duration = Duration::from_millis(50);
+
+/// Expands the macro string into key presses and releases. The first press waits for `delay`.
+pub fn expand(
+    text: &str,
+    hold: Option<Duration>,
+    gap: Option<Duration>,
+    delay: Option<Duration>,
+    fps: f64,
+) -> Result<Vec<Key>, AppError> {
+    let hold = hold.unwrap_or(DEFAULT_TIMING);
+    let gap = gap.unwrap_or(DEFAULT_TIMING);
+
+    let mut keys = Vec::new();
+    let mut wait = None;
+    let mut first = true;
+
+    for (column, item) in tokens(text, 0, ',') {
+        // digit keys and hex codes are keys, other numbers are waits
+        if item.starts_with(|c: char| c.is_ascii_digit()) && keys::vk_code(item).is_none() {
+            let duration = parse_duration(item, fps)
+                .map_err(|_| error(text, column, format!("Invalid wait \"{}\"", item)))?;
+            *wait.get_or_insert_with(Duration::default) += duration;
+            continue;
+        }
+
+        let chord = tokens(item, column - 1, '+')
+            .map(|(column, name)| match keys::vk_code(name) {
+                Some(vk_code) => Ok(vk_code),
+                None if name.is_empty() => Err(error(text, column, "Missing key".to_string())),
+                None => Err(error(text, column, format!("Unknown key \"{}\"", name))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let delay = match (first, wait.take()) {
+            (true, wait) => delay.unwrap_or_default() + wait.unwrap_or_default(),
+            (false, Some(wait)) => wait,
+            (false, None) => gap,
+        };
+        first = false;
+
+        for (index, vk_code) in chord.iter().enumerate() {
+            let delay = Some(delay).filter(|d| index == 0 && *d > Duration::default());
+            keys.push(key(*vk_code, false, delay));
+        }
+        for (index, vk_code) in chord.iter().rev().enumerate() {
+            keys.push(key(*vk_code, true, Some(hold).filter(|_| index == 0)));
+        }
+    }
+
+    Ok(keys)
+}
+
+fn key(vk_code: u32, up: bool, delay: Option<Duration>) -> Key {
+    Key {
+        vk_code,
+        up: Some(up),
+        delay,
+        jitter: None,
+    }
+}
+
+/// Splits the text on the separator, yielding trimmed tokens with their 1-based columns.
+fn tokens(text: &str, offset: usize, separator: char) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = 0;
+    text.split(separator).map(move |part| {
+        let leading = part.chars().take_while(|c| c.is_whitespace()).count();
+        let column = offset + start + leading + 1;
+        start += part.chars().count() + 1;
+        (column, part.trim())
+    })
+}
+
+fn error(text: &str, column: usize, reason: String) -> AppError {
+    AppError::new(format!(
+        "{} at column {} of macro \"{}\"",
+        reason, column, text
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    fn sequence(keys: &[Key]) -> Vec<(u32, bool, Option<u64>)> {
+        keys.iter()
+            .map(|key| {
+                let delay = key.delay.map(|d| d.as_millis() as u64);
+                (key.vk_code, key.up.unwrap(), delay)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_chords_and_waits() {
+        let keys = expand("Ctrl+C, 100ms, Ctrl+V, A", ms(20), ms(30), None, 60.0).unwrap();
+        let bare = expand("Ctrl+C, 100, Ctrl+V, A", ms(20), ms(30), None, 60.0).unwrap();
+        assert_eq!(sequence(&bare), sequence(&keys));
+
+        assert_eq!(
+            sequence(&keys),
+            vec![
+                (0x11, false, None),
+                (0x43, false, None),
+                (0x43, true, Some(20)),
+                (0x11, true, None),
+                (0x11, false, Some(100)),
+                (0x56, false, None),
+                (0x56, true, Some(20)),
+                (0x11, true, None),
+                (0x41, false, Some(30)),
+                (0x41, true, Some(20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_digits_and_codes_as_keys() {
+        let keys = expand("1, 0x41, 25, 2", None, None, None, 60.0).unwrap();
+        let presses: Vec<_> = sequence(&keys)
+            .into_iter()
+            .filter(|(_, up, _)| !up)
+            .collect();
+        assert_eq!(
+            presses,
+            vec![
+                (0x31, false, None),
+                (0x41, false, Some(50)),
+                (0x32, false, Some(25))
+            ]
+        );
+    }
+
+    #[test]
+    fn point_errors_at_tokens() {
+        let err = expand("Ctrl+C, Ctlr+V", None, None, None, 60.0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown key \"Ctlr\" at column 9 of macro \"Ctrl+C, Ctlr+V\""
+        );
+
+        let err = expand("Ctrl+ , A", None, None, None, 60.0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing key at column 6 of macro \"Ctrl+ , A\""
+        );
+
+        let err = expand("A, 5xs", None, None, None, 60.0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid wait \"5xs\" at column 4 of macro \"A, 5xs\""
+        );
+    }
+}
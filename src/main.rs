@@ -1,134 +1,115 @@
+mod check;
+mod control;
+#[cfg(unix)]
+mod dbus;
+mod document;
+mod engine;
 mod errors;
+mod executor;
+mod filter;
+mod keys;
+mod macro_recorder;
+mod macros;
+mod metrics;
+mod migrate;
+mod motion;
 mod profiles;
+mod replay;
+mod scheduler;
+mod schema;
 mod settings;
+mod throttle;
+#[cfg(windows)]
 mod util;
 mod windows;
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-use futures::future;
-use futures::future::AbortHandle;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use tokio::runtime::Builder;
 use tokio::sync::mpsc;
-use tokio::time::sleep;
+use tokio::time;
 
-use crate::profiles::*;
+use crate::control::ControlServer;
+use crate::engine::{Deferred, Engine, EngineState, MatchedEvent, MACRO_QUEUE_SIZE};
+use crate::filter::ChatterFilter;
+use crate::replay::Recorder;
+use crate::scheduler::{Job, Scheduler};
 use crate::settings::Settings;
-use crate::windows::{Hook, HookAction, InputEvent, KeyboardEvent, MouseEvent, Window};
+use crate::windows::Hook;
 
 fn main() {
-    log4rs::init_file("resources/log.toml", Default::default())
-        .expect("Can't load logging config.");
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("--check") => process::exit(check::run()),
+        Some("convert") => process::exit(document::run_convert(&args[1..])),
+        Some("migrate") => process::exit(migrate::run(&args[1..])),
+        Some("schema") => process::exit(schema::run(&args[1..])),
+        Some("fmt") => process::exit(document::run_fmt(&args[1..])),
+        Some("ctl") => process::exit(control::run_client(&args[1..])),
+        Some("replay") => process::exit(replay::run(&args[1..])),
+        Some("record-macro") => process::exit(macro_recorder::run(&args[1..])),
+        _ => {}
+    }
+
+    let log_config = profiles::config_file("log.toml").expect("Can't find log.toml.");
+    log4rs::init_file(log_config, Default::default()).expect("Can't load logging config.");
     log::info!("Starting Keymapper..");
-    let _settings = Settings::load().expect("Can't load settings.");
+    let settings = Settings::load().expect("Can't load settings.");
     let profiles = profiles::load_profiles().expect("Can't load profiles.");
     let profiles = Arc::new(profiles);
 
-    let last_mouse_wheel_time: RefCell<HashMap<bool, Instant>> = RefCell::new(HashMap::new());
-
     let rt = Builder::new_multi_thread()
         .worker_threads(1)
         .enable_all()
         .build()
         .expect("Failed to create Tokio runtime.");
 
-    let (mut tx, rx) = mpsc::channel(100);
+    let (tx, rx) = mpsc::channel(MACRO_QUEUE_SIZE);
 
-    rt.spawn(async { process_event_loop(rx).await });
+    let state = Arc::new(EngineState::default());
+
+    if !settings.metrics.address.is_empty() {
+        rt.spawn(metrics::serve(settings.metrics.address.clone()));
+    }
+
+    let scheduler = Scheduler::start(state.clone(), rt.handle().clone());
+    let rng = match settings.macros.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    rt.spawn(async { process_event_loop(rx, scheduler, rng).await });
+
+    let filter = ChatterFilter::new(&settings.debounce).expect("Can't load debounce settings.");
+    let engine = Arc::new(Mutex::new(Engine::new(profiles, state.clone(), filter, tx)));
+
+    let server = Arc::new(ControlServer::new(
+        engine.clone(),
+        state.clone(),
+        windows::current_thread_id(),
+    ));
+    rt.spawn(control::serve(server.clone()));
+    #[cfg(unix)]
+    rt.spawn(dbus::serve(server, state));
+    rt.spawn(engine::watch_active_profiles(engine.clone()));
+
+    let recorder = match args.as_slice() {
+        [flag, path] if flag == "--record" => {
+            Some(Recorder::create(path).expect("Can't create recording."))
+        }
+        _ => None,
+    };
 
     let _hook = Hook::set_input_hook(move |e| {
-        let action = profiles.iter().enumerate().fold(
-            HookAction::Forward,
-            |result, (profile_index, profile)| {
-                // return early
-                if result == HookAction::Block {
-                    return result;
-                }
-
-                // try another profile
-                let should_process = move || {
-                    profile
-                        .triggers
-                        .iter()
-                        .flat_map(|trigger| match trigger {
-                            &Trigger::Window { ref name } => Window::find(name),
-                        })
-                        .any(|w| w.is_foreground())
-                };
-
-                match e {
-                    InputEvent::Keyboard(e) => {
-                        for (binding_index, binding) in profile.bindings.iter().enumerate() {
-                            if let Binding::Key(binding) = binding {
-                                if is_match(&binding, &e) && !e.syntetic() && should_process() {
-                                    log::trace!(
-                                        "Profile \"{}\" blocked key: {:X} + {:X}",
-                                        profile.name,
-                                        e.vk_code,
-                                        e.flags
-                                    );
-
-                                    if !binding.keys.is_empty() {
-                                        let send_result = tx.try_send(MatchedEvent {
-                                            profiles: profiles.clone(),
-                                            profile_index,
-                                            binding_index,
-                                            up: e.up(),
-                                        });
-
-                                        if let Err(_) = send_result {
-                                            log::error!(
-                                                "Failed to add key macro to processing queue."
-                                            );
-                                        }
-                                    }
-
-                                    return HookAction::Block;
-                                }
-                            }
-                        }
-                    }
-                    InputEvent::Mouse(MouseEvent::MouseWheel { delta, .. }) => {
-                        for binding in &profile.bindings {
-                            if let Binding::MouseWheel(binding) = binding {
-                                let up = *delta > 0;
-                                let matched = binding.up.iter().all(|v| *v == up);
-
-                                if matched && should_process() {
-                                    let now = Instant::now();
-
-                                    let should_throttle =
-                                        match last_mouse_wheel_time.borrow().get(&up) {
-                                            Some(&last) => binding
-                                                .throttle
-                                                .iter()
-                                                .any(|d| d > &now.duration_since(last)),
-                                            _ => false,
-                                        };
-
-                                    if should_throttle {
-                                        log::trace!(
-                                            "Profile \"{}\" throttle mouse wheel (up={})",
-                                            profile.name,
-                                            up
-                                        );
-                                        return HookAction::Block;
-                                    } else {
-                                        last_mouse_wheel_time.borrow_mut().insert(up, now);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-
-                HookAction::Forward
-            },
-        );
-        action
+        let now = Instant::now();
+        if let Some(recorder) = &recorder {
+            recorder.record(e, now);
+        }
+        engine.lock().unwrap().handle(e, now)
     });
 
     windows::message_loop();
@@ -136,56 +117,44 @@ fn main() {
     log::info!("Shutting down Keymapper..");
 }
 
-struct MatchedEvent {
-    profiles: Arc<Vec<Profile>>,
-    profile_index: usize,
-    binding_index: usize,
-    up: bool,
-}
+async fn process_event_loop(
+    mut rx: mpsc::Receiver<MatchedEvent>,
+    scheduler: Scheduler,
+    mut rng: StdRng,
+) {
+    let mut deferred = Deferred::default();
 
-async fn process_event_loop(mut rx: mpsc::Receiver<MatchedEvent>) {
-    let mut event_handle: Option<AbortHandle> = None;
     loop {
-        if let Some(event) = rx.recv().await {
-            if let Some(event_handle) = event_handle {
-                // cancel in progress key event
-                event_handle.abort();
-            }
-
-            // prepare processing new event
-            let event_future = process_event(event);
-
-            // make it abortable
-            let (event_future, abort_handle) = future::abortable(event_future);
-
-            // save the abort handle
-            event_handle = Some(abort_handle);
+        let received = match deferred.next_deadline() {
+            Some(deadline) => tokio::select! {
+                event = rx.recv() => Some(event),
+                _ = time::sleep_until(deadline.into()) => None,
+            },
+            None => Some(rx.recv().await),
+        };
+
+        match received {
+            Some(Some(event)) if event.deadline.is_some() => deferred.push(event),
+            Some(Some(event)) => submit(&scheduler, &event, &mut rng),
+            Some(None) => return,
+            None => {}
+        }
 
-            // start processing detached from this async loop
-            tokio::spawn(event_future);
+        while let Some(event) = deferred.pop(Instant::now()) {
+            submit(&scheduler, &event, &mut rng);
         }
     }
 }
 
-async fn process_event(e: MatchedEvent) {
-    if let Some(profile) = e.profiles.get(e.profile_index) {
-        if let Some(Binding::Key(binding)) = profile.bindings.get(e.binding_index) {
-            for key in &binding.keys {
-                if let Some(duration) = key.delay {
-                    log::trace!("Delaying for {:?}", duration);
-                    sleep(duration).await;
-                }
-                let up = key.up.unwrap_or(e.up);
-                log::trace!("Sending key: {:X}, up = {:?}", key.vk_code, up);
-                windows::send_input_key(key.vk_code as i32, up);
-            }
+fn submit(scheduler: &Scheduler, event: &MatchedEvent, rng: &mut StdRng) {
+    if let Some(profile) = event.profiles.get(event.profile_index) {
+        if let Some(binding) = profile.bindings.get(event.binding_index) {
+            let job = Job {
+                source: format!("Profile \"{}\" {}", profile.name, binding),
+                steps: executor::steps(event, rng),
+                profiles: event.profiles.clone(),
+            };
+            scheduler.submit(job);
         }
     }
 }
-
-fn is_match(binding: &KeyBinding, e: &KeyboardEvent) -> bool {
-    let vcode_matched = binding.vk_code == e.vk_code;
-    let up_matched = binding.up.into_iter().all(|v| v == e.up());
-    let alt_matched = binding.alt.into_iter().all(|v| v == e.alt());
-    vcode_matched && up_matched && alt_matched
-}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+const HOOK_LATENCY_BUCKETS: &[f64] = &[0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.01];
+const STEP_ERROR_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.002, 0.005, 0.0167];
+const MACRO_DURATION_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counters of a running keymapper, rendered in Prometheus text exposition format.
+pub struct Metrics {
+    binding_fired: Mutex<BTreeMap<(String, String), u64>>,
+    blocked: AtomicU64,
+    forwarded: AtomicU64,
+    queue_dropped: AtomicU64,
+    hook_latency: Histogram,
+    macro_duration: Histogram,
+    step_error: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics {
+            binding_fired: Mutex::new(BTreeMap::new()),
+            blocked: AtomicU64::new(0),
+            forwarded: AtomicU64::new(0),
+            queue_dropped: AtomicU64::new(0),
+            hook_latency: Histogram::new(HOOK_LATENCY_BUCKETS),
+            macro_duration: Histogram::new(MACRO_DURATION_BUCKETS),
+            step_error: Histogram::new(STEP_ERROR_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn binding_fired(&self, profile: &str, binding: &str) {
+        let mut fired = self.binding_fired.lock().unwrap();
+        *fired
+            .entry((profile.to_string(), binding.to_string()))
+            .or_insert(0) += 1;
+    }
+
+    /// Records the outcome and time spent in the hook callback.
+    pub fn hook_handled(&self, blocked: bool, latency: Duration) {
+        if blocked {
+            self.blocked.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.forwarded.fetch_add(1, Ordering::Relaxed);
+        }
+        self.hook_latency.observe(latency);
+    }
+
+    pub fn queue_dropped(&self) {
+        self.queue_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn macro_finished(&self, duration: Duration) {
+        self.macro_duration.observe(duration);
+    }
+
+    /// Records how late a macro step was sent.
+    pub fn step_error(&self, error: Duration) {
+        self.step_error.observe(error);
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str("# HELP keymapper_binding_fired_total Bindings that queued their actions.\n");
+        text.push_str("# TYPE keymapper_binding_fired_total counter\n");
+        for ((profile, binding), count) in self.binding_fired.lock().unwrap().iter() {
+            let _ = writeln!(
+                text,
+                "keymapper_binding_fired_total{{profile=\"{}\",binding=\"{}\"}} {}",
+                escape(profile),
+                escape(binding),
+                count
+            );
+        }
+
+        text.push_str("# HELP keymapper_events_total Input events by hook action.\n");
+        text.push_str("# TYPE keymapper_events_total counter\n");
+        let _ = writeln!(
+            text,
+            "keymapper_events_total{{action=\"block\"}} {}",
+            self.blocked.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            text,
+            "keymapper_events_total{{action=\"forward\"}} {}",
+            self.forwarded.load(Ordering::Relaxed)
+        );
+
+        text.push_str("# HELP keymapper_queue_dropped_total Macros dropped by a full queue.\n");
+        text.push_str("# TYPE keymapper_queue_dropped_total counter\n");
+        let _ = writeln!(
+            text,
+            "keymapper_queue_dropped_total {}",
+            self.queue_dropped.load(Ordering::Relaxed)
+        );
+
+        self.hook_latency.render(
+            &mut text,
+            "keymapper_hook_latency_seconds",
+            "Time spent in the input hook callback.",
+        );
+        self.macro_duration.render(
+            &mut text,
+            "keymapper_macro_duration_seconds",
+            "Duration of macros that ran to completion.",
+        );
+        self.step_error.render(
+            &mut text,
+            "keymapper_step_error_seconds",
+            "How late macro steps were sent after their deadline.",
+        );
+
+        text
+    }
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    /// Non-cumulative counts, the last one is above every bound.
+    buckets: Vec<AtomicU64>,
+    /// Sum of the observations in nanoseconds, rendered in seconds.
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, text: &mut String, name: &str, help: &str) {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} histogram", name);
+
+        let mut count = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let le = match self.bounds.get(index) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        let _ = writeln!(text, "{}_sum {}", name, sum);
+        let _ = writeln!(text, "{}_count {}", name, count);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves the metrics over HTTP on the given address.
+pub async fn serve(address: String) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Failed to serve metrics on {}: {}", address, err);
+            return;
+        }
+    };
+    log::info!("Serving metrics on http://{}/metrics", address);
+
+    loop {
+        let mut socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                log::error!("Metrics connection failed: {}", err);
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            // every request gets the metrics, so the request itself is not parsed
+            let mut request = [0; 1024];
+            if socket.read(&mut request).await.is_err() {
+                return;
+            }
+
+            let body = METRICS.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(err) = socket.write_all(response.as_bytes()).await {
+                log::error!("Metrics connection failed: {}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::default();
+        metrics.binding_fired("WoW", "binding \"jump\"");
+        metrics.binding_fired("WoW", "binding \"jump\"");
+        metrics.hook_handled(true, Duration::from_micros(80));
+        metrics.hook_handled(false, Duration::from_millis(20));
+        metrics.hook_handled(false, Duration::from_nanos(700));
+        metrics.queue_dropped();
+
+        let text = metrics.render();
+
+        assert!(text.contains(
+            "keymapper_binding_fired_total{profile=\"WoW\",binding=\"binding \\\"jump\\\"\"} 2"
+        ));
+        assert!(text.contains("keymapper_events_total{action=\"block\"} 1"));
+        assert!(text.contains("keymapper_queue_dropped_total 1"));
+        assert!(text.contains("keymapper_hook_latency_seconds_bucket{le=\"0.0001\"} 2"));
+        assert!(text.contains("keymapper_hook_latency_seconds_bucket{le=\"0.01\"} 2"));
+        assert!(text.contains("keymapper_hook_latency_seconds_bucket{le=\"+Inf\"} 3"));
+        assert!(text.contains("keymapper_hook_latency_seconds_sum 0.0200807\n"));
+        assert!(text.contains("keymapper_hook_latency_seconds_count 3"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Versions of the profiles document. Older documents are upgraded in memory when they load,
+//! `keymapper migrate --write` rewrites the files.
+
+use std::fs;
+use std::path::PathBuf;
+
+use xml::*;
+
+use crate::document::{self, Format};
+use crate::errors::AppError;
+use crate::profiles;
+use crate::windows;
+
+/// Version written into new documents, documents without a `version` are version 1.
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Migrations from version `i + 1` to `i + 2`.
+const MIGRATIONS: [&[fn(&mut Element)]; (CURRENT_VERSION - 1) as usize] =
+    [&[flags_to_alt], &[prefix_hex_codes]];
+
+const LLKHF_ALTDOWN: u32 = 0x20;
+
+const USAGE: &str = "Usage: keymapper migrate [--write] [<file>...]";
+
+/// Upgrades the document to the current version, returning the version it had if it was older.
+pub fn migrate(root: &mut Element) -> Result<Option<u32>, AppError> {
+    let version = match root.get_attribute("version", None) {
+        Some(version) => version
+            .parse()
+            .ok()
+            .filter(|version| *version > 0)
+            .ok_or_else(|| AppError::new(format!("Invalid profiles version {}", version)))?,
+        None => 1,
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(AppError::new(format!(
+            "Profiles version {} is newer than the supported version {}",
+            version, CURRENT_VERSION
+        )));
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+    if let Some(code) = key_name(root) {
+        return Err(AppError::new(format!(
+            "Key code {} of version {} is not hex, key names need version 3",
+            code, version
+        )));
+    }
+
+    for migration in MIGRATIONS[version as usize - 1..].iter().copied().flatten() {
+        migration(root);
+    }
+    root.set_attribute("version".into(), None, CURRENT_VERSION.to_string());
+
+    Ok(Some(version))
+}
+
+/// Version 1 bindings matched Alt-combinations with the raw hook flag, `flags="0x20"`, which
+/// became the `alt` attribute.
+fn flags_to_alt(e: &mut Element) {
+    if e.name == "binding" {
+        if let Some(flags) = e.remove_attribute("flags", None) {
+            match profiles::parse_hex(&flags) {
+                Ok(flags) if flags & LLKHF_ALTDOWN > 0 => {
+                    if e.get_attribute("alt", None).is_none() {
+                        e.set_attribute("alt".into(), None, "true".into());
+                    }
+                    if flags != LLKHF_ALTDOWN {
+                        log::warn!("Dropped flags other than Alt from {:#04X}", flags);
+                    }
+                }
+                _ => log::warn!(
+                    "Dropped flags {} without Alt, they were never matched",
+                    flags
+                ),
+            }
+        }
+    }
+
+    for child in e.children.iter_mut() {
+        if let Xml::ElementNode(child) = child {
+            flags_to_alt(child);
+        }
+    }
+}
+
+/// Version 2 key codes were always hex, with or without `0x`. From version 3 they can be
+/// key names too, so bare hex codes get the prefix. Variables, new in version 3 too, are left
+/// to be substituted.
+fn prefix_hex_codes(e: &mut Element) {
+    for name in key_code_attributes(e) {
+        let bare = e
+            .get_attribute(name, None)
+            .filter(|code| is_bare_hex(code))
+            .map(|code| format!("0x{}", code));
+        if let Some(code) = bare {
+            e.set_attribute(name.to_string(), None, code);
+        }
+    }
+
+    for child in e.children.iter_mut() {
+        if let Xml::ElementNode(child) = child {
+            prefix_hex_codes(child);
+        }
+    }
+}
+
+/// A key code that is neither hex nor a variable, which older versions don't have.
+fn key_name(e: &Element) -> Option<String> {
+    let own = key_code_attributes(e)
+        .iter()
+        .flat_map(|name| e.get_attribute(name, None))
+        .find(|code| !code.contains("${") && profiles::parse_hex(code).is_err())
+        .map(|code| code.to_string());
+
+    own.or_else(|| {
+        e.children.iter().find_map(|child| match child {
+            Xml::ElementNode(child) => key_name(child),
+            _ => None,
+        })
+    })
+}
+
+fn key_code_attributes(e: &Element) -> &'static [&'static str] {
+    match e.name.as_ref() {
+        "binding" | "key" | "token" => &["vk_code"],
+        "motion-input" => &["up", "down", "left", "right"],
+        _ => &[],
+    }
+}
+
+fn is_bare_hex(code: &str) -> bool {
+    !code.is_empty() && code.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Runs `keymapper migrate` and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    match migrate_files(args) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Prints the migrated documents, or with `--write` writes them back to their files.
+fn migrate_files(args: &[String]) -> Result<(), AppError> {
+    let (write, args) = match args {
+        [flag, args @ ..] if flag == "--write" => (true, args),
+        _ => (false, args),
+    };
+    if args.iter().any(|arg| arg.starts_with("--")) {
+        return Err(AppError::new(USAGE));
+    }
+    let paths = if args.is_empty() {
+        profiles::profile_files()?
+    } else {
+        args.iter().map(PathBuf::from).collect()
+    };
+
+    for path in paths {
+        let format = Format::from_path(&path)
+            .ok_or_else(|| AppError::new(format!("Unknown profile format: {}", path.display())))?;
+        let mut root = document::read(&path, &fs::read_to_string(&path)?)?;
+        let version = match migrate(&mut root)
+            .map_err(|err| AppError::new(format!("{}: {}", path.display(), err)))?
+        {
+            Some(version) => version,
+            None => {
+                println!("{} is up to date", path.display());
+                continue;
+            }
+        };
+
+        let text = document::write(&root, format)?;
+        if write {
+            fs::write(&path, text)?;
+            println!(
+                "Migrated {} from version {} to {}",
+                path.display(),
+                version,
+                CURRENT_VERSION
+            );
+        } else {
+            print!("{}", text);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_legacy_alt_flags() {
+        let mut root: Element = r#"<profiles>
+            <profile name="Test">
+                <bindings>
+                    <binding vk_code="0x09" flags="0x20"/>
+                    <binding vk_code="0x0D" flags="0x01"/>
+                </bindings>
+            </profile>
+        </profiles>"#
+            .parse()
+            .unwrap();
+
+        assert_eq!(migrate(&mut root).unwrap(), Some(1));
+        assert_eq!(migrate(&mut root).unwrap(), None);
+
+        let migrated = document::to_xml(&root);
+        assert!(migrated.starts_with(r#"<profiles version="3">"#));
+        assert!(migrated.contains(r#"<binding vk_code="0x09" alt="true"/>"#));
+        assert!(migrated.contains(r#"<binding vk_code="0x0D"/>"#));
+    }
+
+    #[test]
+    fn prefix_bare_hex_codes() {
+        let mut root: Element = r#"<profiles version="2">
+            <profile name="Test">
+                <motion-input up="57" down="0x53"/>
+                <bindings>
+                    <binding vk_code="A"><key vk_code="0x41"/></binding>
+                </bindings>
+            </profile>
+        </profiles>"#
+            .parse()
+            .unwrap();
+
+        assert_eq!(migrate(&mut root).unwrap(), Some(2));
+
+        let migrated = document::to_xml(&root);
+        assert!(migrated.contains(r#"<motion-input up="0x57" down="0x53"/>"#));
+        assert!(migrated.contains(r#"<binding vk_code="0xA">"#));
+        assert!(migrated.contains(r#"<key vk_code="0x41"/>"#));
+    }
+
+    #[test]
+    fn read_codes_without_version_as_hex() {
+        let mut root: Element = r#"<profiles>
+            <profile name="Test">
+                <bindings>
+                    <binding vk_code="C"><key vk_code="F1"/><key vk_code="7"/></binding>
+                </bindings>
+            </profile>
+        </profiles>"#
+            .parse()
+            .unwrap();
+
+        assert_eq!(migrate(&mut root).unwrap(), Some(1));
+        let migrated = document::to_xml(&root);
+        assert!(migrated.contains(r#"<binding vk_code="0xC">"#));
+        assert!(migrated.contains(r#"<key vk_code="0xF1"/>"#));
+        assert!(migrated.contains(r#"<key vk_code="0x7"/>"#));
+
+        // a legacy file loads with its codes as hex, variables included
+        let profiles = profiles::parse_profiles(
+            r#"<profiles>
+                <var name="key" value="0x41"/>
+                <profile name="Test">
+                    <bindings><binding vk_code="C"><key vk_code="${key}"/></binding></bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+        let binding = &profiles[0].bindings[0];
+        assert_eq!(binding.to_string(), "binding 0xC");
+        assert_eq!(binding.actions()[0].to_string(), "key 0x41");
+
+        let mut root: Element = r#"<profiles version="2">
+            <profile name="Test"><bindings><binding vk_code="Tab"/></bindings></profile>
+        </profiles>"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            migrate(&mut root).unwrap_err().to_string(),
+            "Key code Tab of version 2 is not hex, key names need version 3"
+        );
+    }
+
+    #[test]
+    fn reject_newer_versions() {
+        let mut root: Element = r#"<profiles version="4"/>"#.parse().unwrap();
+        assert!(migrate(&mut root).is_err());
+    }
+}
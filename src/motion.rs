@@ -0,0 +1,239 @@
+//! Numpad notation for fighting game motions, e.g. `2 3 6 + LP` for a quarter circle
+//! forward with light punch. Digits are directions as seen on a numpad for a character
+//! facing right (6 is forward, 5 is neutral), `+` presses inputs together and other
+//! tokens are buttons named by the profile.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::errors::AppError;
+use crate::profiles::Key;
+
+/// Keys of a profile that motions expand into.
+#[derive(Debug, Clone, Default)]
+pub struct MotionInput {
+    pub up: Option<u32>,
+    pub down: Option<u32>,
+    pub left: Option<u32>,
+    pub right: Option<u32>,
+    pub tokens: HashMap<String, u32>,
+    /// How long each input is held.
+    pub hold: Option<Duration>,
+    /// Time between the release of an input and the press of the next one.
+    pub gap: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Facing {
+    Right,
+    Left,
+}
+
+const DEFAULT_TIMING: Duration = Duration::from_millis(50);
+
+/// Expands the notation into key presses and releases. The first press waits for `delay`.
+pub fn expand(
+    notation: &str,
+    input: &MotionInput,
+    facing: Facing,
+    hold: Option<Duration>,
+    gap: Option<Duration>,
+    delay: Option<Duration>,
+) -> Result<Vec<Key>, AppError> {
+    let hold = hold.or(input.hold).unwrap_or(DEFAULT_TIMING);
+    let gap = gap.or(input.gap).unwrap_or(DEFAULT_TIMING);
+
+    let mut keys = Vec::new();
+    let mut wait = delay.unwrap_or_default();
+    let mut first = true;
+
+    for step in parse_steps(notation, input, facing)? {
+        // neutral waits for a press
+        if step.is_empty() {
+            wait += hold + gap;
+            continue;
+        }
+
+        let delay = if first { wait } else { gap + wait };
+        first = false;
+        wait = Duration::default();
+
+        for (index, vk_code) in step.iter().enumerate() {
+            keys.push(key(
+                *vk_code,
+                false,
+                Some(delay).filter(|d| index == 0 && *d > Duration::default()),
+            ));
+        }
+        for (index, vk_code) in step.iter().enumerate() {
+            keys.push(key(*vk_code, true, Some(hold).filter(|_| index == 0)));
+        }
+    }
+
+    Ok(keys)
+}
+
+fn key(vk_code: u32, up: bool, delay: Option<Duration>) -> Key {
+    Key {
+        vk_code,
+        up: Some(up),
+        delay,
+        jitter: None,
+    }
+}
+
+/// Splits the notation into steps of keys pressed together.
+fn parse_steps(
+    notation: &str,
+    input: &MotionInput,
+    facing: Facing,
+) -> Result<Vec<Vec<u32>>, AppError> {
+    let invalid = |reason: String| AppError::new(format!("{} in motion \"{}\"", reason, notation));
+
+    let mut steps: Vec<Vec<u32>> = Vec::new();
+    let mut join = false;
+
+    for token in notation.replace('+', " + ").split_whitespace() {
+        if token == "+" {
+            if join || steps.is_empty() {
+                return Err(invalid("Misplaced +".to_string()));
+            }
+            join = true;
+            continue;
+        }
+
+        let items = if token.chars().all(|c| c.is_ascii_digit()) {
+            token
+                .chars()
+                .map(|c| direction_keys(c, input, facing).map_err(&invalid))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            match input.tokens.get(token) {
+                Some(vk_code) => vec![vec![*vk_code]],
+                None => return Err(invalid(format!("Unknown token {}", token))),
+            }
+        };
+
+        for (index, keys) in items.into_iter().enumerate() {
+            match steps.last_mut() {
+                Some(last) if join && index == 0 => {
+                    for vk_code in keys {
+                        if !last.contains(&vk_code) {
+                            last.push(vk_code);
+                        }
+                    }
+                }
+                _ => steps.push(keys),
+            }
+        }
+        join = false;
+    }
+
+    if join {
+        return Err(invalid("Misplaced +".to_string()));
+    }
+
+    Ok(steps)
+}
+
+fn direction_keys(digit: char, input: &MotionInput, facing: Facing) -> Result<Vec<u32>, String> {
+    // numpad notation is written for a character facing right
+    let digit = match (facing, digit) {
+        (Facing::Left, '1') => '3',
+        (Facing::Left, '3') => '1',
+        (Facing::Left, '4') => '6',
+        (Facing::Left, '6') => '4',
+        (Facing::Left, '7') => '9',
+        (Facing::Left, '9') => '7',
+        (_, digit) => digit,
+    };
+
+    let directions: &[(&str, Option<u32>)] = match digit {
+        '1' => &[("down", input.down), ("left", input.left)],
+        '2' => &[("down", input.down)],
+        '3' => &[("down", input.down), ("right", input.right)],
+        '4' => &[("left", input.left)],
+        '5' => &[],
+        '6' => &[("right", input.right)],
+        '7' => &[("up", input.up), ("left", input.left)],
+        '8' => &[("up", input.up)],
+        '9' => &[("up", input.up), ("right", input.right)],
+        _ => return Err(format!("Invalid direction {}", digit)),
+    };
+
+    directions
+        .iter()
+        .map(|(name, key)| key.ok_or_else(|| format!("No {} key in motion-input", name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> MotionInput {
+        MotionInput {
+            up: Some(0x57),
+            down: Some(0x53),
+            left: Some(0x41),
+            right: Some(0x44),
+            tokens: vec![("LP".to_string(), 0x4A)].into_iter().collect(),
+            hold: None,
+            gap: Some(Duration::from_millis(30)),
+        }
+    }
+
+    fn sequence(keys: &[Key]) -> Vec<(u32, bool, Option<u64>)> {
+        keys.iter()
+            .map(|key| {
+                let delay = key.delay.map(|d| d.as_millis() as u64);
+                (key.vk_code, key.up.unwrap(), delay)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_quarter_circle() {
+        let keys = expand("2 3 6 + LP", &input(), Facing::Right, None, None, None).unwrap();
+
+        assert_eq!(
+            sequence(&keys),
+            vec![
+                (0x53, false, None),
+                (0x53, true, Some(50)),
+                (0x53, false, Some(30)),
+                (0x44, false, None),
+                (0x53, true, Some(50)),
+                (0x44, true, None),
+                (0x44, false, Some(30)),
+                (0x4A, false, None),
+                (0x44, true, Some(50)),
+                (0x4A, true, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn mirror_when_facing_left() {
+        let hold = Some(Duration::from_millis(20));
+        let keys = expand("46", &input(), Facing::Left, hold, None, None).unwrap();
+
+        assert_eq!(
+            sequence(&keys),
+            vec![
+                (0x44, false, None),
+                (0x44, true, Some(20)),
+                (0x41, false, Some(30)),
+                (0x41, true, Some(20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_unknown_tokens() {
+        let err = expand("2 HP", &input(), Facing::Right, None, None, None).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown token HP in motion \"2 HP\"");
+        assert!(expand("+ LP", &input(), Facing::Right, None, None, None).is_err());
+        assert!(expand("0", &input(), Facing::Right, None, None, None).is_err());
+    }
+}
@@ -1,14 +1,59 @@
-use std::fs::File;
-use std::io::Read;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::iter;
+use std::ops;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::time::Duration;
 
+use serde::Serialize;
 use xml::*;
 
+use crate::document::{self, Format};
 use crate::errors::AppError;
+use crate::keys;
+use crate::macros;
+use crate::migrate;
+use crate::motion::{self, Facing, MotionInput};
+use crate::windows::MouseButton;
+
+#[derive(Debug)]
+pub struct Profiles {
+    /// Always active bindings, checked before any profile and even while suspended.
+    pub hotkeys: Profile,
+    pub profiles: Vec<Profile>,
+}
+
+impl Profiles {
+    /// Profiles in the order bindings are checked: hotkeys first, at index 0.
+    pub fn iter(&self) -> impl Iterator<Item = &Profile> {
+        iter::once(&self.hotkeys).chain(self.profiles.iter())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Profile> {
+        match index {
+            0 => Some(&self.hotkeys),
+            _ => self.profiles.get(index - 1),
+        }
+    }
+}
+
+impl ops::Index<usize> for Profiles {
+    type Output = Profile;
+
+    fn index(&self, index: usize) -> &Profile {
+        self.get(index).expect("Profile index out of bounds")
+    }
+}
 
 #[derive(Debug)]
 pub struct Profile {
     pub name: String,
+    /// Profile this one was merged onto.
+    pub extends: Option<String>,
     pub triggers: Vec<Trigger>,
     pub bindings: Vec<Binding>,
 }
@@ -21,21 +66,217 @@ pub enum Trigger {
 #[derive(Debug)]
 pub enum Binding {
     Key(KeyBinding),
+    MouseButton(MouseButtonBinding),
     MouseWheel(MouseWheelBinding),
 }
 
+impl Binding {
+    pub fn actions(&self) -> &[Action] {
+        match self {
+            Binding::Key(binding) => &binding.actions,
+            Binding::MouseButton(binding) => &binding.actions,
+            Binding::MouseWheel(binding) => &binding.actions,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Binding::Key(binding) => binding.name.as_deref(),
+            Binding::MouseButton(binding) => binding.name.as_deref(),
+            Binding::MouseWheel(binding) => binding.name.as_deref(),
+        }
+    }
+
+    pub fn throttle(&self) -> Option<Throttle> {
+        match self {
+            Binding::Key(binding) => binding.throttle,
+            Binding::MouseButton(binding) => binding.throttle,
+            Binding::MouseWheel(binding) => binding.throttle,
+        }
+    }
+
+    pub fn jitter(&self) -> Option<Jitter> {
+        match self {
+            Binding::Key(binding) => binding.jitter,
+            Binding::MouseButton(binding) => binding.jitter,
+            Binding::MouseWheel(binding) => binding.jitter,
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.name() {
+            return write!(f, "binding \"{}\"", name);
+        }
+
+        match self {
+            Binding::Key(binding) => write!(f, "binding {:#X}", binding.vk_code),
+            Binding::MouseButton(binding) => write!(f, "mouse-button {:?}", binding.button),
+            Binding::MouseWheel(binding) => match (binding.horizontal, binding.up) {
+                (false, Some(true)) => write!(f, "mouse-wheel up"),
+                (false, Some(false)) => write!(f, "mouse-wheel down"),
+                (true, Some(true)) => write!(f, "mouse-wheel right"),
+                (true, Some(false)) => write!(f, "mouse-wheel left"),
+                (_, None) => write!(f, "mouse-wheel"),
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct KeyBinding {
+    pub name: Option<String>,
     pub vk_code: u32,
     pub up: Option<bool>,
     pub alt: Option<bool>,
-    pub keys: Vec<Key>,
+    pub throttle: Option<Throttle>,
+    /// Default jitter of the action delays.
+    pub jitter: Option<Jitter>,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug)]
+pub struct MouseButtonBinding {
+    pub name: Option<String>,
+    pub button: MouseButton,
+    pub up: Option<bool>,
+    pub throttle: Option<Throttle>,
+    pub jitter: Option<Jitter>,
+    pub actions: Vec<Action>,
 }
 
 #[derive(Debug)]
 pub struct MouseWheelBinding {
+    pub name: Option<String>,
+    /// Matches wheel up (or tilt right, for horizontal bindings) when true.
     pub up: Option<bool>,
-    pub throttle: Option<Duration>,
+    pub horizontal: bool,
+    pub throttle: Option<Throttle>,
+    pub jitter: Option<Jitter>,
+    /// Multiplier applied to every wheel delta before accumulating it.
+    pub scale: f32,
+    /// Accumulated delta that fires the actions once.
+    pub step: u32,
+    pub actions: Vec<Action>,
+}
+
+impl MouseWheelBinding {
+    /// Adds the delta to the remainder and returns how many steps it completed.
+    pub fn accumulate(&self, remainder: &mut f32, delta: i16) -> u32 {
+        let delta = delta as f32 * self.scale;
+
+        // scrolling in the opposite direction discards a partial step
+        if *remainder != 0.0 && remainder.signum() != delta.signum() {
+            *remainder = 0.0;
+        }
+
+        *remainder += delta;
+        let steps = (remainder.abs() / self.step as f32).floor();
+        *remainder -= remainder.signum() * steps * self.step as f32;
+        steps as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Throttle {
+    pub window: Duration,
+    pub mode: ThrottleMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThrottleMode {
+    /// The first event passes, the rest are dropped until the window has elapsed.
+    Leading,
+    /// Events are held back until the window passes without another one, then the last fires.
+    Trailing,
+    /// At most `limit` events pass within any window.
+    Sliding { limit: usize },
+}
+
+/// Random offset added to action delays, so they don't repeat exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Jitter {
+    pub range: Duration,
+    pub mode: JitterMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JitterMode {
+    /// Offsets spread evenly within the range either way.
+    Uniform,
+    /// Normally distributed offsets with half the range as standard deviation, cut at the range.
+    Gaussian,
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Key(Key),
+    Button(Button),
+    Move(Move),
+    Scroll(Scroll),
+    Run(Run),
+    Control(Control),
+}
+
+impl Action {
+    pub fn delay(&self) -> Option<Duration> {
+        match self {
+            Action::Key(key) => key.delay,
+            Action::Button(button) => button.delay,
+            Action::Move(movement) => movement.delay,
+            Action::Scroll(scroll) => scroll.delay,
+            Action::Run(run) => run.delay,
+            Action::Control(control) => control.delay,
+        }
+    }
+
+    fn delay_mut(&mut self) -> &mut Option<Duration> {
+        match self {
+            Action::Key(key) => &mut key.delay,
+            Action::Button(button) => &mut button.delay,
+            Action::Move(movement) => &mut movement.delay,
+            Action::Scroll(scroll) => &mut scroll.delay,
+            Action::Run(run) => &mut run.delay,
+            Action::Control(control) => &mut control.delay,
+        }
+    }
+
+    /// Jitter of the delay, overriding the one of the binding.
+    pub fn jitter(&self) -> Option<Jitter> {
+        match self {
+            Action::Key(key) => key.jitter,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let up = |up: Option<bool>| match up {
+            Some(true) => " up",
+            Some(false) => " down",
+            None => "",
+        };
+
+        match self {
+            Action::Key(key) => write!(f, "key {:#X}{}", key.vk_code, up(key.up))?,
+            Action::Button(button) => write!(f, "button {:?}{}", button.button, up(button.up))?,
+            Action::Move(movement) if movement.absolute => {
+                write!(f, "move to {},{}", movement.x, movement.y)?
+            }
+            Action::Move(movement) => write!(f, "move by {},{}", movement.x, movement.y)?,
+            Action::Scroll(scroll) if scroll.horizontal => write!(f, "hscroll {}", scroll.delta)?,
+            Action::Scroll(scroll) => write!(f, "scroll {}", scroll.delta)?,
+            Action::Run(run) => write!(f, "run {}", run.program)?,
+            Action::Control(control) => write!(f, "{:?}", control.command)?,
+        }
+
+        match self.delay() {
+            Some(delay) => write!(f, " after {}ms", delay.as_millis()),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -43,30 +284,695 @@ pub struct Key {
     pub vk_code: u32,
     pub up: Option<bool>,
     pub delay: Option<Duration>,
+    pub jitter: Option<Jitter>,
 }
 
-pub fn load_profiles() -> Result<Vec<Profile>, AppError> {
-    let mut file = File::open("resources/profiles.xml")?;
-    let mut buffer = String::new();
-    file.read_to_string(&mut buffer)?;
+#[derive(Debug, Clone, Copy)]
+pub struct Button {
+    pub button: MouseButton,
+    pub up: Option<bool>,
+    pub delay: Option<Duration>,
+}
 
-    let root: Element = buffer
-        .parse()
-        .map_err(|_| AppError::new("Error parsing profiles.xml"))?;
+#[derive(Debug, Clone, Copy)]
+pub struct Move {
+    pub x: i32,
+    pub y: i32,
+    pub absolute: bool,
+    pub delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Scroll {
+    pub delta: i32,
+    pub horizontal: bool,
+    pub delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    pub program: String,
+    pub args: Vec<String>,
+    pub working_dir: Option<String>,
+    /// Wait for the program to exit before the next action, otherwise leave it running.
+    pub wait: bool,
+    /// Kill the program if it's still running after this time.
+    #[serde(skip)]
+    pub timeout: Option<Duration>,
+    #[serde(skip)]
+    pub delay: Option<Duration>,
+}
+
+/// Controls keymapper itself. Runs on press only, never on release.
+#[derive(Debug, Clone)]
+pub struct Control {
+    pub command: ControlCommand,
+    pub delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlCommand {
+    Suspend,
+    Resume,
+    ToggleSuspend,
+    /// Keep the named profile active regardless of its triggers, or stop doing so if None.
+    ActivateProfile(Option<String>),
+    /// Activate the next profile in order, or none after the last one.
+    CycleProfiles,
+}
+
+impl ControlCommand {
+    /// Suspend commands are applied by the engine as their binding matches, not queued.
+    pub fn is_suspend(&self) -> bool {
+        matches!(
+            self,
+            ControlCommand::Suspend | ControlCommand::Resume | ControlCommand::ToggleSuspend
+        )
+    }
+}
+
+pub fn load_profiles() -> Result<Profiles, AppError> {
+    load_files(profile_files()?)
+}
+
+fn load_files(paths: Vec<PathBuf>) -> Result<Profiles, AppError> {
+    let sources = paths
+        .into_iter()
+        .map(|path| match fs::read_to_string(&path) {
+            Ok(text) => Ok((path, text)),
+            Err(err) => Err(AppError::new(format!(
+                "Can't read {}: {}",
+                path.display(),
+                err
+            ))),
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    parse_sources(&sources)
+}
+
+/// Directories searched for profiles in order: the per-user config directory, then the
+/// resources next to the binary.
+pub fn config_dirs() -> Vec<PathBuf> {
+    let user_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+
+    user_dir
+        .map(|dir| dir.join("keymapper"))
+        .into_iter()
+        .chain(resources_dir())
+        .collect()
+}
+
+/// The file of the first config directory holding one by the name.
+pub fn config_file(name: &str) -> Option<PathBuf> {
+    config_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The `resources` directory next to the binary.
+fn resources_dir() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    exe.parent().map(|dir| dir.join("resources"))
+}
+
+/// Profile files of the first config directory holding any: `profiles.xml` (or `.toml`,
+/// `.yaml`) followed by the files in `profiles.d` in name order.
+pub fn profile_files() -> Result<Vec<PathBuf>, AppError> {
+    for dir in config_dirs() {
+        let files = profile_files_in(&dir)?;
+        if !files.is_empty() {
+            return Ok(files);
+        }
+    }
+
+    Err(AppError::new(format!(
+        "No profiles file or profiles.d found in {}",
+        config_dirs()
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+fn profile_files_in(dir: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+
+    for extension in &["xml", "toml", "yaml", "yml"] {
+        let main = dir.join("profiles").with_extension(extension);
+        if main.is_file() {
+            files.push(main);
+        }
+    }
+
+    let included = dir.join("profiles.d");
+    if included.is_dir() {
+        let mut entries = fs::read_dir(&included)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.retain(|path| path.is_file() && Format::from_path(path).is_some());
+        entries.sort();
+        files.extend(entries);
+    }
+
+    Ok(files)
+}
+
+/// Parses a single profiles document.
+#[cfg(test)]
+pub fn parse_profiles(text: &str) -> Result<Profiles, AppError> {
+    parse_sources(&[(PathBuf::from("profiles.xml"), text.to_string())])
+}
+
+/// Parses profile files as one document. The `fps` of a root applies to the profiles and
+/// hotkeys of its file, 60 when it has none, other root attributes of earlier files take
+/// precedence. Profile
+/// names, macro ids and global variables must be unique across all of them.
+pub fn parse_sources(sources: &[(PathBuf, String)]) -> Result<Profiles, AppError> {
+    let mut documents = Vec::new();
+    for (path, text) in sources {
+        let mut document = document::read(path, text)?;
+        let version = migrate::migrate(&mut document)
+            .map_err(|err| AppError::new(format!("{}: {}", path.display(), err)))?;
+        if let Some(version) = version {
+            log::info!(
+                "Upgraded {} from version {}, keymapper migrate --write updates the file",
+                path.display(),
+                version
+            );
+        }
+        documents.push((path.as_path(), document));
+    }
+
+    let mut defined_in: HashMap<(&str, String), &Path> = HashMap::new();
+    for (path, document) in &documents {
+        for e in document.children.iter().flat_map(as_element) {
+            let names: Vec<(&str, &str)> = match e.name.as_ref() {
+                "profile" => vec![("Profile", e.get_attribute("name", None).unwrap_or(""))],
+                "macros" => e
+                    .children
+                    .iter()
+                    .flat_map(as_element)
+                    .flat_map(|e| e.get_attribute("id", None))
+                    .map(|id| ("Macro", id))
+                    .collect(),
+                "var" => vec![("Variable", e.get_attribute("name", None).unwrap_or(""))],
+                _ => vec![],
+            };
+
+            for (kind, name) in names {
+                if let Some(first) = defined_in.insert((kind, name.to_string()), path) {
+                    let message = if first == *path {
+                        format!("{} {} is defined twice in {}", kind, name, path.display())
+                    } else {
+                        format!(
+                            "{} {} is defined in both {} and {}",
+                            kind,
+                            name,
+                            first.display(),
+                            path.display()
+                        )
+                    };
+                    return Err(AppError::new(message));
+                }
+            }
+        }
+    }
+
+    let mut globals = HashMap::new();
+    let mut profile_paths = HashMap::new();
+    for (path, document) in &documents {
+        read_vars(document, &mut globals, path, "/profiles")?;
+        for e in document.children.iter().flat_map(as_element) {
+            if e.name == "profile" {
+                let name = e.get_attribute("name", None).unwrap_or("");
+                profile_paths.insert(name.to_string(), *path);
+            }
+        }
+    }
+
+    let mut root: Option<Element> = None;
+    for (path, mut document) in documents {
+        substitute_vars(&mut document, &globals, path, "/profiles")?;
+
+        match &mut root {
+            None => root = Some(document),
+            Some(root) => {
+                // the fps of the main file is not the default of the others
+                let fps = document
+                    .remove_attribute("fps", None)
+                    .unwrap_or_else(|| ReadContext::default().fps.to_string());
+                set_fps(&mut document, &fps);
+                for (name, value) in document.attributes {
+                    root.attributes.entry(name).or_insert(value);
+                }
+                root.children.extend(document.children);
+            }
+        }
+    }
+
+    let root = root.unwrap_or_else(|| Element::new("profiles".into(), None, vec![]));
+    let resolved = resolve_profiles(&root, &globals, &profile_paths)?;
+    read_profiles(&root, resolved)
+}
+
+/// Sets the frame rate of the profiles and hotkeys of a document that don't have their own.
+fn set_fps(root: &mut Element, fps: &str) {
+    for e in root.children.iter_mut().flat_map(as_element_mut) {
+        if (e.name == "profile" || e.name == "hotkeys") && e.get_attribute("fps", None).is_none() {
+            e.set_attribute("fps".into(), None, fps.to_string());
+        }
+    }
+}
+
+/// Adds the `<var name value/>` children of the element to the variables in scope. Values can
+/// use the variables defined before them.
+fn read_vars(
+    e: &Element,
+    vars: &mut HashMap<String, String>,
+    path: &Path,
+    location: &str,
+) -> Result<(), AppError> {
+    let mut defined = HashSet::new();
+    for (i, var) in e
+        .children
+        .iter()
+        .flat_map(as_element)
+        .filter(|child| child.name == "var")
+        .enumerate()
+    {
+        let location = format!("{}/var[{}]", location, i + 1);
+        let at = |message: String| {
+            AppError::new(format!("{} in {} at {}", message, path.display(), location))
+        };
+
+        let name = var
+            .get_attribute("name", None)
+            .ok_or_else(|| at("name is missing from var".to_string()))?;
+        let value = var
+            .get_attribute("value", None)
+            .ok_or_else(|| at(format!("value is missing from var {}", name)))?;
+        if !defined.insert(name) {
+            return Err(at(format!("Variable {} is defined twice", name)));
+        }
+        let value = substitute(value, vars).map_err(at)?;
+        vars.insert(name.to_string(), value);
+    }
+
+    Ok(())
+}
+
+/// Replaces `${name}` in the attributes of the element and its children. Profiles in it are
+/// left for after inheritance, they add their own variables to the global ones.
+fn substitute_vars(
+    e: &mut Element,
+    vars: &HashMap<String, String>,
+    path: &Path,
+    location: &str,
+) -> Result<(), AppError> {
+    if e.name == "var" {
+        return Ok(());
+    }
+
+    for ((name, _), value) in e.attributes.iter_mut() {
+        if value.contains('$') {
+            *value = substitute(value, vars).map_err(|message| {
+                AppError::new(format!(
+                    "{} in {} at {}/@{}",
+                    message,
+                    path.display(),
+                    location,
+                    name
+                ))
+            })?;
+        }
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for child in e.children.iter_mut().flat_map(as_element_mut) {
+        if child.name == "profile" {
+            continue;
+        }
+        let count = counts.entry(child.name.clone()).or_insert(0);
+        *count += 1;
+        let location = format!("{}/{}[{}]", location, child.name, count);
+        substitute_vars(child, vars, path, &location)?;
+    }
 
-    read_children(&root, read_profile)
+    Ok(())
 }
 
-fn read_profile(e: &Element) -> Result<Profile, AppError> {
+/// Replaces every `${name}` in the text with the value of the variable, `$${` stands for a
+/// literal `${`.
+fn substitute(text: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        if !rest.starts_with("${") {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let end = rest
+            .find('}')
+            .ok_or_else(|| format!("Unclosed variable in {}", text))?;
+        let name = &rest[2..end];
+        let value = vars
+            .get(name)
+            .ok_or_else(|| format!("Undefined variable {}", name))?;
+        result.push_str(value);
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Reads the merged document, with its profile elements already resolved.
+fn read_profiles(root: &Element, resolved: Vec<Element>) -> Result<Profiles, AppError> {
+    let mut ctx = ReadContext::default().with_fps(root)?;
+    let mut macros = HashMap::new();
+    for e in root.children.iter().flat_map(as_element) {
+        if e.name == "macros" {
+            for definition in e.children.iter().flat_map(as_element) {
+                let id = definition
+                    .get_attribute("id", None)
+                    .ok_or_else(|| AppError::new("id is missing from macro definition"))?;
+                if macros.insert(id.to_string(), definition.clone()).is_some() {
+                    return Err(AppError::new(format!("Macro {} is defined twice", id)));
+                }
+            }
+        }
+    }
+    check_macro_cycles(&macros)?;
+    ctx.macros = Rc::new(macros);
+    let ctx = &ctx;
+
+    let mut resolved = resolved.into_iter();
+    let mut hotkeys = Vec::new();
+    let mut profiles = Vec::new();
+
+    for e in root.children.iter().flat_map(as_element) {
+        match e.name.as_ref() {
+            "macros" | "var" => {}
+            "hotkeys" => {
+                let ctx = &ctx.with_fps(e)?;
+                hotkeys.extend(read_children(e, |e| read_binding(e, ctx))?)
+            }
+            "profile" => {
+                let e = resolved.next().unwrap_or_else(|| e.clone());
+                profiles.push(read_profile(&e, ctx)?)
+            }
+            _ => return Err(AppError::new(format!("Unknown element: {}", e.name))),
+        }
+    }
+
+    let profiles = Profiles {
+        hotkeys: Profile {
+            name: "hotkeys".to_string(),
+            extends: None,
+            triggers: vec![],
+            bindings: hotkeys,
+        },
+        profiles,
+    };
+
+    validate_profiles(&profiles)?;
+
+    Ok(profiles)
+}
+
+/// Merges profiles onto the profiles they extend, returning the profile elements in order
+/// with their variables replaced. Variables of a profile shadow those of its parents, which
+/// shadow the global ones, so inherited bindings use the values of the child.
+fn resolve_profiles(
+    root: &Element,
+    globals: &HashMap<String, String>,
+    paths: &HashMap<String, &Path>,
+) -> Result<Vec<Element>, AppError> {
+    let elements: Vec<&Element> = root
+        .children
+        .iter()
+        .flat_map(as_element)
+        .filter(|e| e.name == "profile")
+        .collect();
+
+    let mut by_name = HashMap::new();
+    for e in &elements {
+        let name = e.get_attribute("name", None).unwrap_or("");
+        if by_name.insert(name, *e).is_some() {
+            return Err(AppError::new(format!("Profile {} is defined twice", name)));
+        }
+    }
+
+    let scope = Scope {
+        by_name: &by_name,
+        globals,
+        paths,
+    };
+    elements
+        .iter()
+        .map(|e| {
+            let (mut resolved, vars) = resolve_profile(e, &scope, &mut vec![])?;
+            let (path, location) = scope.location(e);
+            substitute_vars(&mut resolved, &vars, path, &location)?;
+            Ok(resolved)
+        })
+        .collect()
+}
+
+/// What resolving a profile looks up.
+struct Scope<'a> {
+    by_name: &'a HashMap<&'a str, &'a Element>,
+    globals: &'a HashMap<String, String>,
+    /// File that defines each profile, for errors.
+    paths: &'a HashMap<String, &'a Path>,
+}
+
+impl Scope<'_> {
+    fn location(&self, e: &Element) -> (&Path, String) {
+        let name = e.get_attribute("name", None).unwrap_or("");
+        let path = self
+            .paths
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| Path::new(""));
+        (path, format!("/profiles/profile[@name='{}']", name))
+    }
+}
+
+/// Merges the profile onto its parents, returning it with the variables in its scope.
+fn resolve_profile(
+    e: &Element,
+    scope: &Scope,
+    stack: &mut Vec<String>,
+) -> Result<(Element, HashMap<String, String>), AppError> {
+    let name = e.get_attribute("name", None).unwrap_or("");
+    let (resolved, mut vars) = match e.get_attribute("extends", None) {
+        None => (e.clone(), scope.globals.clone()),
+        Some(parent_name) => {
+            stack.push(name.to_string());
+            if stack[..stack.len() - 1].iter().any(|n| n == name) {
+                return Err(AppError::new(format!(
+                    "Profile inheritance cycle: {}",
+                    stack.join(" -> ")
+                )));
+            }
+
+            let parent = scope.by_name.get(parent_name).ok_or_else(|| {
+                AppError::new(format!(
+                    "Profile {} extends unknown profile {}",
+                    name, parent_name
+                ))
+            })?;
+            let (parent, vars) = resolve_profile(parent, scope, stack)?;
+            stack.pop();
+            (merge_profile(parent, e), vars)
+        }
+    };
+
+    let (path, location) = scope.location(e);
+    read_vars(e, &mut vars, path, &location)?;
+    Ok((resolved, vars))
+}
+
+/// Child attributes, triggers and motion-input replace the parent ones, bindings replace
+/// parent bindings matching the same input and add the rest.
+fn merge_profile(mut merged: Element, child: &Element) -> Element {
+    merged.attributes.extend(child.attributes.clone());
+
+    // variables are scoped by resolve_profile instead
+    let sections = child.children.iter().flat_map(as_element);
+    for section in sections.filter(|e| e.name != "var") {
+        let existing = merged
+            .children
+            .iter_mut()
+            .flat_map(as_element_mut)
+            .find(|e| e.name == section.name);
+
+        match existing {
+            Some(existing) if section.name == "bindings" => {
+                for binding in section.children.iter().flat_map(as_element) {
+                    let signature = binding_signature(binding);
+                    let overridden = existing
+                        .children
+                        .iter_mut()
+                        .flat_map(as_element_mut)
+                        .find(|e| binding_signature(e) == signature);
+                    match overridden {
+                        Some(overridden) => *overridden = binding.clone(),
+                        None => existing.children.push(Xml::ElementNode(binding.clone())),
+                    }
+                }
+            }
+            Some(existing) => *existing = section.clone(),
+            None => merged.children.push(Xml::ElementNode(section.clone())),
+        }
+    }
+
+    merged
+}
+
+/// Bindings with the same signature match the same input, compared by their parsed values
+/// so that `0x9`, `0x09` and `Tab` are the same key. Invalid values fail when read.
+fn binding_signature(e: &Element) -> (&str, Option<u32>, Option<bool>, Option<bool>) {
+    let flag = |name| e.get_attribute(name, None).and_then(|v| v.parse().ok());
+    let (input, up) = match e.name.as_ref() {
+        "binding" => (
+            e.get_attribute("vk_code", None)
+                .and_then(|v| parse_key_code(v).ok()),
+            flag("up"),
+        ),
+        "mouse-button" => (
+            e.get_attribute("button", None)
+                .and_then(|v| parse_mouse_button(v).ok())
+                .map(|button| button as u32),
+            flag("up"),
+        ),
+        _ => match read_wheel_direction(e) {
+            Ok((horizontal, up)) => (Some(horizontal as u32), up),
+            Err(_) => (None, None),
+        },
+    };
+    (&e.name, input, up, flag("alt"))
+}
+
+fn validate_profiles(profiles: &Profiles) -> Result<(), AppError> {
+    let actions = profiles
+        .iter()
+        .flat_map(|profile| profile.bindings.iter())
+        .flat_map(|binding| binding.actions());
+
+    for action in actions {
+        if let Action::Control(Control {
+            command: ControlCommand::ActivateProfile(Some(name)),
+            ..
+        }) = action
+        {
+            if !profiles
+                .profiles
+                .iter()
+                .any(|profile| &profile.name == name)
+            {
+                return Err(AppError::new(format!(
+                    "activate-profile refers to unknown profile {}",
+                    name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings inherited by the elements being read.
+#[derive(Debug, Clone)]
+struct ReadContext {
+    /// Frame rate of durations given in frames.
+    fps: f64,
+    motion_input: Option<Rc<MotionInput>>,
+    /// Macro definitions by id.
+    macros: Rc<HashMap<String, Element>>,
+    /// Macros already expanded, shared by all the contexts of a file.
+    expansions: Rc<RefCell<HashMap<String, Vec<MacroExpansion>>>>,
+}
+
+/// Actions of a macro definition as expanded at a frame rate and motion-input.
+#[derive(Debug)]
+struct MacroExpansion {
+    fps: f64,
+    motion_input: Option<Rc<MotionInput>>,
+    actions: Vec<Action>,
+}
+
+/// Limits the actions of an expanded macro, references can nest to exponential sizes.
+const MAX_MACRO_ACTIONS: usize = 100_000;
+
+impl Default for ReadContext {
+    fn default() -> ReadContext {
+        ReadContext {
+            fps: 60.0,
+            motion_input: None,
+            macros: Rc::new(HashMap::new()),
+            expansions: Rc::default(),
+        }
+    }
+}
+
+impl ReadContext {
+    fn with_fps(&self, e: &Element) -> Result<ReadContext, AppError> {
+        match e.get_attribute("fps", None) {
+            Some(fps) => {
+                let fps = fps
+                    .parse()
+                    .ok()
+                    .filter(|fps: &f64| *fps > 0.0)
+                    .ok_or_else(|| AppError::new(format!("Invalid fps {}", fps)))?;
+                Ok(ReadContext {
+                    fps,
+                    ..self.clone()
+                })
+            }
+            None => Ok(self.clone()),
+        }
+    }
+}
+
+fn read_profile(e: &Element, ctx: &ReadContext) -> Result<Profile, AppError> {
     let profile_name = e.get_attribute("name", None).unwrap_or("").to_string();
+    let extends = e.get_attribute("extends", None).map(|s| s.to_string());
+    let mut ctx = ctx.with_fps(e)?;
+    if let Some(motion_input) = e.get_child("motion-input", None) {
+        ctx.motion_input = Some(Rc::new(read_motion_input(motion_input, &ctx)?));
+    }
+    let ctx = &ctx;
 
     let triggers = read_section(e, "triggers", read_trigger)?;
-    let bindings = read_section(e, "bindings", read_binding)?;
+    let bindings = read_section(e, "bindings", |e| read_binding(e, ctx))?;
 
     Ok(Profile {
         name: profile_name,
-        triggers: triggers,
-        bindings: bindings,
+        extends,
+        triggers,
+        bindings,
     })
 }
 
@@ -79,10 +985,11 @@ fn read_trigger(e: &Element) -> Result<Trigger, AppError> {
     Ok(Trigger::Window { name: window_name })
 }
 
-fn read_binding(e: &Element) -> Result<Binding, AppError> {
+fn read_binding(e: &Element, ctx: &ReadContext) -> Result<Binding, AppError> {
     match e.name.as_ref() {
-        "binding" => read_key_binding(e).map(Binding::Key),
-        "mouse-wheel" => read_mouse_wheel_binding(e).map(Binding::MouseWheel),
+        "binding" => read_key_binding(e, ctx).map(Binding::Key),
+        "mouse-button" => read_mouse_button_binding(e, ctx).map(Binding::MouseButton),
+        "mouse-wheel" => read_mouse_wheel_binding(e, ctx).map(Binding::MouseWheel),
         _ => Err(AppError::new(format!(
             "Unknown binding element: {}",
             e.name
@@ -90,58 +997,689 @@ fn read_binding(e: &Element) -> Result<Binding, AppError> {
     }
 }
 
-fn read_key_binding(e: &Element) -> Result<KeyBinding, AppError> {
+fn read_key_binding(e: &Element, ctx: &ReadContext) -> Result<KeyBinding, AppError> {
+    let name = e.get_attribute("name", None).map(|s| s.to_string());
+
     let vk_code = e
         .get_attribute("vk_code", None)
         .ok_or_else(|| AppError::new("vcode is missing from binding"))?;
-    let vk_code = parse_hex(vk_code)?;
+    let vk_code = parse_key_code(vk_code)?;
 
     let up = e.get_attribute("up", None).and_then(|s| s.parse().ok());
 
     let alt = e.get_attribute("alt", None).and_then(|s| s.parse().ok());
 
-    let keys = e
-        .get_children("key", None)
-        .map(read_key)
-        .collect::<Result<_, _>>()?;
+    let throttle = read_throttle(e, ctx)?;
+
+    let jitter = read_jitter(e, ctx)?;
+
+    let actions = read_actions(e, ctx)?;
 
     Ok(KeyBinding {
+        name,
         vk_code,
         up,
         alt,
-        keys,
+        throttle,
+        jitter,
+        actions,
+    })
+}
+
+fn read_mouse_button_binding(
+    e: &Element,
+    ctx: &ReadContext,
+) -> Result<MouseButtonBinding, AppError> {
+    let name = e.get_attribute("name", None).map(|s| s.to_string());
+
+    let button = e
+        .get_attribute("button", None)
+        .ok_or_else(|| AppError::new("button is missing from mouse-button"))?;
+    let button = parse_mouse_button(button)?;
+
+    let up = e.get_attribute("up", None).and_then(|s| s.parse().ok());
+
+    let throttle = read_throttle(e, ctx)?;
+
+    let jitter = read_jitter(e, ctx)?;
+
+    let actions = read_actions(e, ctx)?;
+
+    Ok(MouseButtonBinding {
+        name,
+        button,
+        up,
+        throttle,
+        jitter,
+        actions,
+    })
+}
+
+fn read_mouse_wheel_binding(e: &Element, ctx: &ReadContext) -> Result<MouseWheelBinding, AppError> {
+    let name = e.get_attribute("name", None).map(|s| s.to_string());
+    let (horizontal, up) = read_wheel_direction(e)?;
+    let throttle = read_throttle(e, ctx)?;
+    let jitter = read_jitter(e, ctx)?;
+    let scale = e
+        .get_attribute("scale", None)
+        .unwrap_or("1")
+        .parse()
+        .map_err(|_| AppError::new("Invalid mouse-wheel scale"))?;
+    let step = e
+        .get_attribute("step", None)
+        .unwrap_or("120")
+        .parse()
+        .ok()
+        .filter(|step| *step > 0)
+        .ok_or_else(|| AppError::new("Invalid mouse-wheel step"))?;
+    let actions = read_actions(e, ctx)?;
+    Ok(MouseWheelBinding {
+        name,
+        up,
+        horizontal,
+        throttle,
+        jitter,
+        scale,
+        step,
+        actions,
+    })
+}
+
+/// Whether a mouse-wheel binding is horizontal, and its direction.
+fn read_wheel_direction(e: &Element) -> Result<(bool, Option<bool>), AppError> {
+    match e.get_attribute("direction", None) {
+        Some("up") => Ok((false, Some(true))),
+        Some("down") => Ok((false, Some(false))),
+        Some("right") => Ok((true, Some(true))),
+        Some("left") => Ok((true, Some(false))),
+        Some(direction) => Err(AppError::new(format!(
+            "Invalid mouse-wheel direction {}",
+            direction
+        ))),
+        None => Ok((
+            e.get_attribute("horizontal", None)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            e.get_attribute("up", None).and_then(|v| v.parse().ok()),
+        )),
+    }
+}
+
+fn read_throttle(e: &Element, ctx: &ReadContext) -> Result<Option<Throttle>, AppError> {
+    let window = match read_duration(e, "throttle", ctx)? {
+        Some(window) => window,
+        None => return Ok(None),
+    };
+
+    let mode = match e.get_attribute("throttle_mode", None) {
+        None | Some("leading") => ThrottleMode::Leading,
+        Some("trailing") => ThrottleMode::Trailing,
+        Some("sliding") => {
+            let limit = e
+                .get_attribute("throttle_limit", None)
+                .unwrap_or("1")
+                .parse()
+                .ok()
+                .filter(|limit| *limit > 0)
+                .ok_or_else(|| AppError::new("Invalid throttle_limit"))?;
+            ThrottleMode::Sliding { limit }
+        }
+        Some(mode) => return Err(AppError::new(format!("Invalid throttle_mode {}", mode))),
+    };
+
+    Ok(Some(Throttle { window, mode }))
+}
+
+fn read_jitter(e: &Element, ctx: &ReadContext) -> Result<Option<Jitter>, AppError> {
+    let range = match read_duration(e, "jitter", ctx)? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    let mode = match e.get_attribute("jitter_mode", None) {
+        None | Some("uniform") => JitterMode::Uniform,
+        Some("gaussian") => JitterMode::Gaussian,
+        Some(mode) => return Err(AppError::new(format!("Invalid jitter_mode {}", mode))),
+    };
+
+    Ok(Some(Jitter { range, mode }))
+}
+
+fn read_motion_input(e: &Element, ctx: &ReadContext) -> Result<MotionInput, AppError> {
+    let direction = |name| e.get_attribute(name, None).map(parse_key_code).transpose();
+
+    let tokens = read_children(e, |token| {
+        let name = token
+            .get_attribute("name", None)
+            .ok_or_else(|| AppError::new("name is missing from token"))?;
+        let vk_code = token
+            .get_attribute("vk_code", None)
+            .ok_or_else(|| AppError::new(format!("vk_code is missing from token {}", name)))?;
+        Ok((name.to_string(), parse_key_code(vk_code)?))
+    })?;
+
+    Ok(MotionInput {
+        up: direction("up")?,
+        down: direction("down")?,
+        left: direction("left")?,
+        right: direction("right")?,
+        tokens: tokens.into_iter().collect(),
+        hold: read_duration(e, "hold", ctx)?,
+        gap: read_duration(e, "gap", ctx)?,
     })
 }
 
-fn read_mouse_wheel_binding(e: &Element) -> Result<MouseWheelBinding, AppError> {
-    let up = e.get_attribute("up", None).and_then(|v| v.parse().ok());
-    let throttle = e
-        .get_attribute("throttle", None)
-        .and_then(|v| v.parse().ok())
-        .map(Duration::from_millis);
-    Ok(MouseWheelBinding { up, throttle })
+fn read_actions(e: &Element, ctx: &ReadContext) -> Result<Vec<Action>, AppError> {
+    let actions = read_children(e, |e| read_action(e, ctx))?;
+    Ok(actions.into_iter().flatten().collect())
+}
+
+/// Reads an action element, motions and macros expand into several actions.
+fn read_action(e: &Element, ctx: &ReadContext) -> Result<Vec<Action>, AppError> {
+    if e.name == "macro" && e.get_attribute("ref", None).is_some() {
+        return read_macro_ref(e, ctx);
+    }
+
+    let keys = match e.name.as_ref() {
+        "motion" => Some(read_motion(e, ctx)?),
+        "macro" => Some(read_macro(e, ctx)?),
+        _ => None,
+    };
+    if let Some(keys) = keys {
+        return Ok(keys.into_iter().map(Action::Key).collect());
+    }
+
+    let action = match e.name.as_ref() {
+        "key" => read_key(e, ctx).map(Action::Key),
+        "button" => read_button(e, ctx).map(Action::Button),
+        "move" => read_move(e, ctx).map(Action::Move),
+        "scroll" => read_scroll(e, ctx).map(Action::Scroll),
+        "run" => read_run(e, ctx).map(Action::Run),
+        "suspend" => read_control(e, ctx, ControlCommand::Suspend),
+        "resume" => read_control(e, ctx, ControlCommand::Resume),
+        "toggle-suspend" => read_control(e, ctx, ControlCommand::ToggleSuspend),
+        "activate-profile" => {
+            let name = e.get_attribute("name", None).map(|s| s.to_string());
+            read_control(e, ctx, ControlCommand::ActivateProfile(name))
+        }
+        "cycle-profiles" => read_control(e, ctx, ControlCommand::CycleProfiles),
+        _ => Err(AppError::new(format!("Unknown action element: {}", e.name))),
+    };
+    action.map(|action| vec![action])
 }
 
-fn read_key(e: &Element) -> Result<Key, AppError> {
+/// Expands a reference to a macro definition in the context of the caller.
+fn read_macro_ref(e: &Element, ctx: &ReadContext) -> Result<Vec<Action>, AppError> {
+    let id = e.get_attribute("ref", None).unwrap_or("");
+    let mut actions = expand_macro(id, ctx)?;
+
+    if let (Some(delay), Some(first)) = (read_delay(e, ctx)?, actions.first_mut()) {
+        let first_delay = first.delay_mut();
+        *first_delay = Some(first_delay.unwrap_or_default() + delay);
+    }
+
+    Ok(actions)
+}
+
+/// Expands a macro definition once per frame rate and motion-input.
+fn expand_macro(id: &str, ctx: &ReadContext) -> Result<Vec<Action>, AppError> {
+    let definition = ctx
+        .macros
+        .get(id)
+        .ok_or_else(|| AppError::new(format!("Unknown macro {}", id)))?;
+
+    let same_context = |expansion: &&MacroExpansion| {
+        expansion.fps == ctx.fps
+            && match (&expansion.motion_input, &ctx.motion_input) {
+                (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+    };
+    if let Some(expansion) = ctx
+        .expansions
+        .borrow()
+        .get(id)
+        .and_then(|expansions| expansions.iter().find(same_context))
+    {
+        return Ok(expansion.actions.clone());
+    }
+
+    let actions = if definition.get_attribute("keys", None).is_some() {
+        read_macro(definition, ctx)?
+            .into_iter()
+            .map(Action::Key)
+            .collect()
+    } else {
+        read_actions(definition, ctx)?
+    };
+    if actions.len() > MAX_MACRO_ACTIONS {
+        return Err(AppError::new(format!(
+            "Macro {} expands to more than {} actions",
+            id, MAX_MACRO_ACTIONS
+        )));
+    }
+
+    ctx.expansions
+        .borrow_mut()
+        .entry(id.to_string())
+        .or_default()
+        .push(MacroExpansion {
+            fps: ctx.fps,
+            motion_input: ctx.motion_input.clone(),
+            actions: actions.clone(),
+        });
+    Ok(actions)
+}
+
+/// Rejects macro definitions that reference themselves, whether used or not.
+fn check_macro_cycles(macros: &HashMap<String, Element>) -> Result<(), AppError> {
+    fn refs(e: &Element, found: &mut Vec<String>) {
+        for child in e.children.iter().flat_map(as_element) {
+            match child.get_attribute("ref", None) {
+                Some(id) if child.name == "macro" => found.push(id.to_string()),
+                _ => refs(child, found),
+            }
+        }
+    }
+
+    fn visit<'a>(
+        id: &'a str,
+        macros: &'a HashMap<String, Element>,
+        stack: &mut Vec<&'a str>,
+        checked: &mut HashSet<&'a str>,
+    ) -> Result<(), AppError> {
+        if let Some(start) = stack.iter().position(|caller| *caller == id) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(id);
+            return Err(AppError::new(format!(
+                "Macro cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        // unknown references fail when they are expanded
+        let (id, definition) = match macros.get_key_value(id) {
+            Some(entry) if !checked.contains(id) => entry,
+            _ => return Ok(()),
+        };
+
+        let mut found = vec![];
+        refs(definition, &mut found);
+        stack.push(id);
+        for callee in &found {
+            if let Some((callee, _)) = macros.get_key_value(callee.as_str()) {
+                visit(callee, macros, stack, checked)?;
+            }
+        }
+        stack.pop();
+        checked.insert(id);
+        Ok(())
+    }
+
+    let mut ids: Vec<&String> = macros.keys().collect();
+    ids.sort();
+    let mut checked = HashSet::new();
+    for id in ids {
+        visit(id, macros, &mut vec![], &mut checked)?;
+    }
+    Ok(())
+}
+
+fn read_macro(e: &Element, ctx: &ReadContext) -> Result<Vec<Key>, AppError> {
+    let keys = e
+        .get_attribute("keys", None)
+        .ok_or_else(|| AppError::new("keys is missing from macro"))?;
+
+    macros::expand(
+        keys,
+        read_duration(e, "hold", ctx)?,
+        read_duration(e, "gap", ctx)?,
+        read_delay(e, ctx)?,
+        ctx.fps,
+    )
+}
+
+fn read_motion(e: &Element, ctx: &ReadContext) -> Result<Vec<Key>, AppError> {
+    let notation = e
+        .get_attribute("notation", None)
+        .ok_or_else(|| AppError::new("notation is missing from motion"))?;
+
+    let motion_input = ctx
+        .motion_input
+        .as_ref()
+        .ok_or_else(|| AppError::new("motion needs a motion-input in its profile"))?;
+
+    let facing = match e.get_attribute("facing", None) {
+        None | Some("right") => Facing::Right,
+        Some("left") => Facing::Left,
+        Some(facing) => return Err(AppError::new(format!("Invalid motion facing {}", facing))),
+    };
+
+    motion::expand(
+        notation,
+        motion_input,
+        facing,
+        read_duration(e, "hold", ctx)?,
+        read_duration(e, "gap", ctx)?,
+        read_delay(e, ctx)?,
+    )
+}
+
+fn read_key(e: &Element, ctx: &ReadContext) -> Result<Key, AppError> {
     let vcode = e
         .get_attribute("vk_code", None)
         .ok_or_else(|| AppError::new("vcode is missing from key"))?;
-    let vcode = parse_hex(vcode)?;
+    let vcode = parse_key_code(vcode)?;
 
     let up = e.get_attribute("up", None).and_then(|s| s.parse().ok());
 
-    let delay = e
-        .get_attribute("delay", None)
-        .and_then(|v| v.parse().ok())
-        .map(Duration::from_millis);
+    let delay = read_delay(e, ctx)?;
+
+    let jitter = read_jitter(e, ctx)?;
 
     Ok(Key {
         vk_code: vcode,
         up,
         delay,
+        jitter,
     })
 }
 
+fn read_button(e: &Element, ctx: &ReadContext) -> Result<Button, AppError> {
+    let button = e
+        .get_attribute("name", None)
+        .ok_or_else(|| AppError::new("name is missing from button"))?;
+    let button = parse_mouse_button(button)?;
+
+    let up = e.get_attribute("up", None).and_then(|s| s.parse().ok());
+
+    let delay = read_delay(e, ctx)?;
+
+    Ok(Button { button, up, delay })
+}
+
+fn read_move(e: &Element, ctx: &ReadContext) -> Result<Move, AppError> {
+    let x = e
+        .get_attribute("x", None)
+        .unwrap_or("0")
+        .parse()
+        .map_err(|_| AppError::new("Invalid x coordinate in move"))?;
+    let y = e
+        .get_attribute("y", None)
+        .unwrap_or("0")
+        .parse()
+        .map_err(|_| AppError::new("Invalid y coordinate in move"))?;
+
+    let absolute = e
+        .get_attribute("absolute", None)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(false);
+
+    let delay = read_delay(e, ctx)?;
+
+    Ok(Move {
+        x,
+        y,
+        absolute,
+        delay,
+    })
+}
+
+fn read_scroll(e: &Element, ctx: &ReadContext) -> Result<Scroll, AppError> {
+    let delta = e
+        .get_attribute("delta", None)
+        .ok_or_else(|| AppError::new("delta is missing from scroll"))?
+        .parse()
+        .map_err(|_| AppError::new("Invalid delta in scroll"))?;
+
+    let horizontal = e
+        .get_attribute("horizontal", None)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(false);
+
+    let delay = read_delay(e, ctx)?;
+
+    Ok(Scroll {
+        delta,
+        horizontal,
+        delay,
+    })
+}
+
+fn read_run(e: &Element, ctx: &ReadContext) -> Result<Run, AppError> {
+    let program = e
+        .get_attribute("program", None)
+        .ok_or_else(|| AppError::new("program is missing from run"))?
+        .to_string();
+
+    let args = split_args(e.get_attribute("args", None).unwrap_or(""))?;
+
+    let working_dir = e.get_attribute("working_dir", None).map(|s| s.to_string());
+
+    let wait = e
+        .get_attribute("wait", None)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(false);
+
+    let timeout = read_duration(e, "timeout", ctx)?;
+
+    let delay = read_delay(e, ctx)?;
+
+    Ok(Run {
+        program,
+        args,
+        working_dir,
+        wait,
+        timeout,
+        delay,
+    })
+}
+
+fn read_control(
+    e: &Element,
+    ctx: &ReadContext,
+    command: ControlCommand,
+) -> Result<Action, AppError> {
+    let delay = read_delay(e, ctx)?;
+    Ok(Action::Control(Control { command, delay }))
+}
+
+fn read_delay(e: &Element, ctx: &ReadContext) -> Result<Option<Duration>, AppError> {
+    read_duration(e, "delay", ctx)
+}
+
+fn read_duration(
+    e: &Element,
+    attribute: &str,
+    ctx: &ReadContext,
+) -> Result<Option<Duration>, AppError> {
+    match e.get_attribute(attribute, None) {
+        Some(text) => parse_duration(text, ctx.fps)
+            .map(Some)
+            .map_err(|err| AppError::new(format!("Invalid {} in {}: {}", attribute, e.name, err))),
+        None => Ok(None),
+    }
+}
+
+/// Writes loaded profiles back out, with inheritance and macros expanded.
+pub fn write_profiles(profiles: &Profiles) -> Element {
+    let version = migrate::CURRENT_VERSION.to_string();
+    let mut root = new_element("profiles", vec![("version", version)]);
+    if !profiles.hotkeys.bindings.is_empty() {
+        let hotkeys = profiles.hotkeys.bindings.iter().map(write_binding);
+        root.children
+            .push(Xml::ElementNode(new_section("hotkeys", hotkeys)));
+    }
+    for profile in &profiles.profiles {
+        root.children.push(Xml::ElementNode(write_profile(profile)));
+    }
+    root
+}
+
+fn write_profile(profile: &Profile) -> Element {
+    let mut e = new_element("profile", vec![("name", profile.name.clone())]);
+    let triggers = profile.triggers.iter().map(|trigger| match trigger {
+        Trigger::Window { name } => new_element("window", vec![("name", name.clone())]),
+    });
+    let bindings = profile.bindings.iter().map(write_binding);
+    e.children
+        .push(Xml::ElementNode(new_section("triggers", triggers)));
+    e.children
+        .push(Xml::ElementNode(new_section("bindings", bindings)));
+    e
+}
+
+fn write_binding(binding: &Binding) -> Element {
+    let mut attributes = vec![];
+    if let Some(name) = binding.name() {
+        attributes.push(("name", name.to_string()));
+    }
+
+    let element_name = match binding {
+        Binding::Key(binding) => {
+            attributes.push(("vk_code", format_hex(binding.vk_code)));
+            push_some(&mut attributes, "up", binding.up);
+            push_some(&mut attributes, "alt", binding.alt);
+            "binding"
+        }
+        Binding::MouseButton(binding) => {
+            attributes.push(("button", format_mouse_button(binding.button).to_string()));
+            push_some(&mut attributes, "up", binding.up);
+            "mouse-button"
+        }
+        Binding::MouseWheel(binding) => {
+            if binding.horizontal {
+                attributes.push(("horizontal", "true".to_string()));
+            }
+            push_some(&mut attributes, "up", binding.up);
+            if binding.scale != 1.0 {
+                attributes.push(("scale", binding.scale.to_string()));
+            }
+            if binding.step != 120 {
+                attributes.push(("step", binding.step.to_string()));
+            }
+            "mouse-wheel"
+        }
+    };
+
+    if let Some(throttle) = binding.throttle() {
+        attributes.push(("throttle", format_duration(throttle.window)));
+        match throttle.mode {
+            ThrottleMode::Leading => {}
+            ThrottleMode::Trailing => attributes.push(("throttle_mode", "trailing".to_string())),
+            ThrottleMode::Sliding { limit } => {
+                attributes.push(("throttle_mode", "sliding".to_string()));
+                attributes.push(("throttle_limit", limit.to_string()));
+            }
+        }
+    }
+    push_jitter(&mut attributes, binding.jitter());
+
+    let mut e = new_element(element_name, attributes);
+    e.children.extend(
+        binding
+            .actions()
+            .iter()
+            .map(|action| Xml::ElementNode(write_action(action))),
+    );
+    e
+}
+
+fn write_action(action: &Action) -> Element {
+    let mut attributes = vec![];
+    let name = match action {
+        Action::Key(key) => {
+            attributes.push(("vk_code", format_hex(key.vk_code)));
+            push_some(&mut attributes, "up", key.up);
+            push_jitter(&mut attributes, key.jitter);
+            "key"
+        }
+        Action::Button(button) => {
+            attributes.push(("name", format_mouse_button(button.button).to_string()));
+            push_some(&mut attributes, "up", button.up);
+            "button"
+        }
+        Action::Move(movement) => {
+            attributes.push(("x", movement.x.to_string()));
+            attributes.push(("y", movement.y.to_string()));
+            if movement.absolute {
+                attributes.push(("absolute", "true".to_string()));
+            }
+            "move"
+        }
+        Action::Scroll(scroll) => {
+            attributes.push(("delta", scroll.delta.to_string()));
+            if scroll.horizontal {
+                attributes.push(("horizontal", "true".to_string()));
+            }
+            "scroll"
+        }
+        Action::Run(run) => {
+            attributes.push(("program", run.program.clone()));
+            if !run.args.is_empty() {
+                attributes.push(("args", join_args(&run.args)));
+            }
+            if let Some(working_dir) = &run.working_dir {
+                attributes.push(("working_dir", working_dir.clone()));
+            }
+            if run.wait {
+                attributes.push(("wait", "true".to_string()));
+            }
+            if let Some(timeout) = run.timeout {
+                attributes.push(("timeout", format_duration(timeout)));
+            }
+            "run"
+        }
+        Action::Control(control) => match &control.command {
+            ControlCommand::Suspend => "suspend",
+            ControlCommand::Resume => "resume",
+            ControlCommand::ToggleSuspend => "toggle-suspend",
+            ControlCommand::ActivateProfile(profile) => {
+                if let Some(profile) = profile {
+                    attributes.push(("name", profile.clone()));
+                }
+                "activate-profile"
+            }
+            ControlCommand::CycleProfiles => "cycle-profiles",
+        },
+    };
+
+    if let Some(delay) = action.delay() {
+        attributes.push(("delay", format_duration(delay)));
+    }
+    new_element(name, attributes)
+}
+
+fn push_some(attributes: &mut Vec<(&str, String)>, name: &'static str, value: Option<bool>) {
+    if let Some(value) = value {
+        attributes.push((name, value.to_string()));
+    }
+}
+
+fn push_jitter(attributes: &mut Vec<(&str, String)>, jitter: Option<Jitter>) {
+    if let Some(jitter) = jitter {
+        attributes.push(("jitter", format_duration(jitter.range)));
+        if jitter.mode == JitterMode::Gaussian {
+            attributes.push(("jitter_mode", "gaussian".to_string()));
+        }
+    }
+}
+
+fn new_element(name: &str, attributes: Vec<(&str, String)>) -> Element {
+    Element::new(
+        name.to_string(),
+        None,
+        attributes
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), None, value)),
+    )
+}
+
+fn new_section<I: Iterator<Item = Element>>(name: &str, children: I) -> Element {
+    let mut e = new_element(name, vec![]);
+    e.children.extend(children.map(Xml::ElementNode));
+    e
+}
+
 fn read_section<T, F>(
     elem: &Element,
     section_name: &str,
@@ -163,15 +1701,136 @@ where
     let children: Result<Vec<T>, AppError> = elem
         .children
         .iter()
-        .flat_map(|elem| match elem {
-            &Xml::ElementNode(ref elem) => Some(element_reader(elem)),
-            _ => None,
-        })
+        .flat_map(as_element)
+        .map(element_reader)
         .collect();
     children
 }
 
-fn parse_hex(text: &str) -> Result<u32, AppError> {
+fn as_element(node: &Xml) -> Option<&Element> {
+    match node {
+        Xml::ElementNode(elem) => Some(elem),
+        _ => None,
+    }
+}
+
+fn as_element_mut(node: &mut Xml) -> Option<&mut Element> {
+    match node {
+        Xml::ElementNode(elem) => Some(elem),
+        _ => None,
+    }
+}
+
+/// Splits command line arguments on whitespace, keeping double-quoted text together.
+fn split_args(text: &str) -> Result<Vec<String>, AppError> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quoted = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quoted {
+        return Err(AppError::new(format!("Unclosed quote in args {}", text)));
+    }
+
+    args.extend(arg);
+    Ok(args)
+}
+
+/// Quotes arguments holding whitespace, the inverse of `split_args`.
+fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(
+            |arg| match arg.contains(char::is_whitespace) || arg.is_empty() {
+                true => format!("\"{}\"", arg),
+                false => arg.clone(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses a duration in milliseconds, or with a unit: `ms`, `s` or `f` for frames at `fps`.
+pub fn parse_duration(text: &str, fps: f64) -> Result<Duration, AppError> {
+    let invalid = || AppError::new(format!("invalid duration {}", text));
+
+    // the same grammar as the schemas: digits, maybe a fraction, and a unit, spaces around it
+    let spaces: &[char] = &[' ', '\t', '\n', '\r'];
+    let text = text.trim_matches(spaces);
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    let well_formed = match number.split_once('.') {
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(number),
+    };
+    if !well_formed {
+        return Err(invalid());
+    }
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+
+    let seconds = match unit.trim_start_matches(spaces) {
+        "" | "ms" => number / 1000.0,
+        "s" => number,
+        "f" => number / fps,
+        _ => return Err(invalid()),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+pub fn parse_mouse_button(text: &str) -> Result<MouseButton, AppError> {
+    match text {
+        "left" => Ok(MouseButton::Left),
+        "right" => Ok(MouseButton::Right),
+        "middle" => Ok(MouseButton::Middle),
+        "x1" => Ok(MouseButton::X1),
+        "x2" => Ok(MouseButton::X2),
+        _ => Err(AppError::new(format!("Invalid mouse button {}", text))),
+    }
+}
+
+/// Writes a duration in milliseconds, down to nanoseconds so that frames read back the same.
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    match nanos % 1_000_000 {
+        0 => (nanos / 1_000_000).to_string(),
+        fraction => format!("{}.{:06}", nanos / 1_000_000, fraction)
+            .trim_end_matches('0')
+            .to_string(),
+    }
+}
+
+pub fn format_mouse_button(button: MouseButton) -> &'static str {
+    match button {
+        MouseButton::Left => "left",
+        MouseButton::Right => "right",
+        MouseButton::Middle => "middle",
+        MouseButton::X1 => "x1",
+        MouseButton::X2 => "x2",
+    }
+}
+
+pub fn format_hex(value: u32) -> String {
+    format!("{:#04X}", value).replacen("0X", "0x", 1)
+}
+
+/// Parses a virtual key code, given in hex like `0x41` or by name like `A`.
+pub fn parse_key_code(text: &str) -> Result<u32, AppError> {
+    keys::vk_code(text).ok_or_else(|| AppError::new(format!("Invalid key {}", text)))
+}
+
+pub fn parse_hex(text: &str) -> Result<u32, AppError> {
     let text = text.trim_start_matches("0x");
     u32::from_str_radix(text, 16).map_err(|_| AppError::new(format!("Invalid hex number {}", text)))
 }
@@ -182,7 +1841,719 @@ mod tests {
 
     #[test]
     fn load_profiles_works() {
-        let profiles = load_profiles();
+        let files = profile_files_in(Path::new("resources")).unwrap();
+        let profiles = load_files(files);
         assert!(profiles.is_ok());
     }
+
+    #[test]
+    fn read_mouse_actions() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x70">
+                            <button name="x1" up="false"/>
+                            <move x="-10" y="5" delay="20"/>
+                            <scroll delta="-120" horizontal="true"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        match &profiles[0].bindings[0] {
+            Binding::Key(binding) => match binding.actions.as_slice() {
+                [Action::Button(button), Action::Move(movement), Action::Scroll(scroll)] => {
+                    assert_eq!(button.button, MouseButton::X1);
+                    assert_eq!(button.up, Some(false));
+                    assert_eq!((movement.x, movement.y, movement.absolute), (-10, 5, false));
+                    assert_eq!(movement.delay, Some(Duration::from_millis(20)));
+                    assert_eq!((scroll.delta, scroll.horizontal), (-120, true));
+                }
+                actions => panic!("Unexpected actions: {:?}", actions),
+            },
+            binding => panic!("Unexpected binding: {:?}", binding),
+        }
+    }
+
+    #[test]
+    fn read_mouse_button_binding() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <mouse-button button="x2" up="false">
+                            <key vk_code="0x20"/>
+                        </mouse-button>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        match &profiles[0].bindings[0] {
+            Binding::MouseButton(binding) => {
+                assert_eq!(binding.button, MouseButton::X2);
+                assert_eq!(binding.up, Some(false));
+                assert_eq!(binding.actions.len(), 1);
+            }
+            binding => panic!("Unexpected binding: {:?}", binding),
+        }
+    }
+
+    #[test]
+    fn read_mouse_wheel_binding() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <mouse-wheel direction="left" scale="0.5" step="60">
+                            <key vk_code="0x31"/>
+                        </mouse-wheel>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        match &profiles[0].bindings[0] {
+            Binding::MouseWheel(binding) => {
+                assert_eq!((binding.horizontal, binding.up), (true, Some(false)));
+                assert_eq!((binding.scale, binding.step), (0.5, 60));
+                assert_eq!(binding.actions.len(), 1);
+            }
+            binding => panic!("Unexpected binding: {:?}", binding),
+        }
+    }
+
+    #[test]
+    fn accumulate_mouse_wheel_steps() {
+        let binding = MouseWheelBinding {
+            name: None,
+            up: None,
+            horizontal: false,
+            throttle: None,
+            jitter: None,
+            scale: 2.0,
+            step: 120,
+            actions: vec![],
+        };
+        let mut remainder = 0.0;
+
+        assert_eq!(binding.accumulate(&mut remainder, 40), 0);
+        assert_eq!(binding.accumulate(&mut remainder, 40), 1);
+        assert_eq!(binding.accumulate(&mut remainder, 120), 2);
+        assert_eq!(remainder, 40.0);
+
+        // changing direction drops the partial step
+        assert_eq!(binding.accumulate(&mut remainder, -40), 0);
+        assert_eq!(remainder, -80.0);
+    }
+
+    #[test]
+    fn read_throttle_modes() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x41" throttle="100"/>
+                        <binding vk_code="0x42" throttle="100" throttle_mode="trailing"/>
+                        <mouse-button button="left" throttle="1000" throttle_mode="sliding" throttle_limit="5"/>
+                        <mouse-wheel/>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let modes: Vec<_> = profiles[0]
+            .bindings
+            .iter()
+            .map(|b| b.throttle().map(|t| t.mode))
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                Some(ThrottleMode::Leading),
+                Some(ThrottleMode::Trailing),
+                Some(ThrottleMode::Sliding { limit: 5 }),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn read_run_action() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding name="screenshot" vk_code="0x2C">
+                            <run program="capture.exe" args='--region "full screen" -q' wait="true" timeout="5000"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let binding = &profiles[0].bindings[0];
+        assert_eq!(binding.to_string(), "binding \"screenshot\"");
+
+        match binding.actions() {
+            [Action::Run(run)] => {
+                assert_eq!(run.program, "capture.exe");
+                assert_eq!(run.args, vec!["--region", "full screen", "-q"]);
+                assert!(run.wait);
+                assert_eq!(run.timeout, Some(Duration::from_millis(5000)));
+            }
+            actions => panic!("Unexpected actions: {:?}", actions),
+        }
+    }
+
+    #[test]
+    fn split_quoted_args() {
+        assert_eq!(split_args("").unwrap(), Vec::<String>::new());
+        assert_eq!(split_args(r#"a  "b c" """#).unwrap(), vec!["a", "b c", ""]);
+        assert!(split_args(r#"a "b"#).is_err());
+    }
+
+    #[test]
+    fn read_hotkeys_and_controls() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <hotkeys>
+                    <binding vk_code="0x13"><toggle-suspend/></binding>
+                    <binding vk_code="0x91"><cycle-profiles/></binding>
+                </hotkeys>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x70"><activate-profile name="Test"/></binding>
+                        <binding vk_code="0x71"><activate-profile/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap();
+
+        let commands: Vec<_> = profiles
+            .iter()
+            .flat_map(|profile| profile.bindings.iter())
+            .flat_map(|binding| binding.actions())
+            .map(|action| match action {
+                Action::Control(control) => control.command.clone(),
+                action => panic!("Unexpected action: {:?}", action),
+            })
+            .collect();
+
+        assert_eq!(
+            commands,
+            vec![
+                ControlCommand::ToggleSuspend,
+                ControlCommand::CycleProfiles,
+                ControlCommand::ActivateProfile(Some("Test".to_string())),
+                ControlCommand::ActivateProfile(None),
+            ]
+        );
+        assert_eq!(profiles.get(1).map(|p| p.name.as_str()), Some("Test"));
+    }
+
+    #[test]
+    fn reject_unknown_activated_profile() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <hotkeys>
+                    <binding vk_code="0x13"><activate-profile name="Missing"/></binding>
+                </hotkeys>
+            </profiles>"#,
+        );
+        assert!(profiles.is_err());
+    }
+
+    #[test]
+    fn reject_unknown_action() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x70"><jump/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        );
+        assert!(profiles.is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_duration("50", 60.0).unwrap(), ms(50));
+        assert_eq!(parse_duration("50ms", 60.0).unwrap(), ms(50));
+        assert_eq!(parse_duration("1.5s", 60.0).unwrap(), ms(1500));
+        assert_eq!(parse_duration("3f", 60.0).unwrap(), ms(50));
+        assert_eq!(parse_duration("3f", 30.0).unwrap(), ms(100));
+        assert!(parse_duration("-5", 60.0).is_err());
+        assert!(parse_duration("5 frames", 60.0).is_err());
+        assert!(parse_duration(&"9".repeat(30), 60.0).is_err());
+        assert!(parse_duration("3f", 0.0).is_err());
+    }
+
+    #[test]
+    fn read_frame_delays_at_profile_fps() {
+        let profiles = parse_profiles(
+            r#"<profiles fps="30">
+                <profile name="A">
+                    <bindings>
+                        <binding vk_code="0x31"><key vk_code="0x41" delay="3f"/></binding>
+                    </bindings>
+                </profile>
+                <profile name="B" fps="60">
+                    <bindings>
+                        <binding vk_code="0x31"><key vk_code="0x41" delay="3f"/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let delays: Vec<_> = profiles
+            .iter()
+            .map(|profile| profile.bindings[0].actions()[0].delay())
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(50))
+            ]
+        );
+        assert!(parse_profiles(r#"<profiles fps="0"/>"#).is_err());
+    }
+
+    #[test]
+    fn expand_macro_references() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <macros>
+                    <macro id="tap-a" keys="A"/>
+                    <macro id="twice">
+                        <macro ref="tap-a"/>
+                        <macro ref="tap-a" delay="10"/>
+                    </macro>
+                </macros>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x31"><macro ref="twice" delay="5"/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let delays: Vec<_> = profiles[0].bindings[0]
+            .actions()
+            .iter()
+            .map(|action| action.delay().map(|d| d.as_millis()))
+            .collect();
+        assert_eq!(delays, vec![Some(5), Some(50), Some(10), Some(50)]);
+    }
+
+    #[test]
+    fn reject_macro_cycles() {
+        let err = parse_profiles(
+            r#"<profiles>
+                <macros>
+                    <macro id="a"><macro ref="b"/></macro>
+                    <macro id="b"><macro ref="a"/></macro>
+                </macros>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x31"><macro ref="a"/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "Macro cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn reject_cycles_in_unused_macros() {
+        let err = parse_profiles(
+            r#"<profiles>
+                <macros>
+                    <macro id="used" keys="A"/>
+                    <macro id="loop"><key vk_code="0x41"/><macro ref="loop"/></macro>
+                </macros>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x31"><macro ref="used"/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "Macro cycle: loop -> loop");
+    }
+
+    #[test]
+    fn expand_macros_once_per_context() {
+        let mut macros = r#"<macro id="m0" keys="A, 2f, B"/>"#.to_string();
+        for level in 1..64 {
+            macros += &format!(
+                r#"<macro id="m{}"><macro ref="m{1}"/><macro ref="m{1}"/></macro>"#,
+                level,
+                level - 1
+            );
+        }
+        let text = format!(
+            r#"<profiles>
+                <macros>{}</macros>
+                <profile name="A" fps="30">
+                    <bindings><binding vk_code="0x31"><macro ref="m2"/></binding></bindings>
+                </profile>
+                <profile name="B">
+                    <bindings><binding vk_code="0x31"><macro ref="m2"/></binding></bindings>
+                </profile>
+                <profile name="C">
+                    <bindings><binding vk_code="0x31"><macro ref="{{}}"/></binding></bindings>
+                </profile>
+            </profiles>"#,
+            macros
+        );
+
+        let profiles = parse_profiles(&text.replace("{}", "m2")).unwrap().profiles;
+        let delays = |profile: &Profile| -> Vec<_> {
+            profile.bindings[0]
+                .actions()
+                .iter()
+                .map(|action| action.delay().map(|d| d.as_millis()))
+                .collect()
+        };
+        assert_eq!(delays(&profiles[0]).len(), 16);
+        assert_eq!(delays(&profiles[0])[2], Some(66));
+        assert_eq!(delays(&profiles[1])[2], Some(33));
+
+        let err = parse_profiles(&text.replace("{}", "m63")).unwrap_err();
+        assert!(err.to_string().contains("expands to more than"));
+    }
+
+    #[test]
+    fn inherit_and_override_bindings() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <profile name="Base">
+                    <triggers><window name="Base"/></triggers>
+                    <bindings>
+                        <binding vk_code="0x5B"/>
+                        <binding vk_code="0x09" alt="true"/>
+                    </bindings>
+                </profile>
+                <profile name="Child" extends="Base">
+                    <bindings>
+                        <binding vk_code="0x09" alt="true"><key vk_code="0x08"/></binding>
+                        <binding vk_code="0x14"/>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let child = &profiles[1];
+        assert_eq!(child.name, "Child");
+        assert_eq!(child.extends.as_deref(), Some("Base"));
+        assert_eq!(child.triggers.len(), 1);
+
+        let bindings: Vec<_> = child
+            .bindings
+            .iter()
+            .map(|binding| (binding.to_string(), binding.actions().len()))
+            .collect();
+        assert_eq!(
+            bindings,
+            vec![
+                ("binding 0x5B".to_string(), 0),
+                ("binding 0x9".to_string(), 1),
+                ("binding 0x14".to_string(), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn override_bindings_by_parsed_input() {
+        let profiles = parse_profiles(
+            r#"<profiles version="3">
+                <profile name="Base">
+                    <bindings>
+                        <binding vk_code="0x9" up="true"/>
+                        <binding vk_code="0x41"/>
+                        <mouse-wheel direction="up"/>
+                    </bindings>
+                </profile>
+                <profile name="Child" extends="Base">
+                    <bindings>
+                        <binding vk_code="Tab" up="true"><key vk_code="0x08"/></binding>
+                        <binding vk_code="a"><key vk_code="0x08"/></binding>
+                        <mouse-wheel up="true" horizontal="false"><key vk_code="0x08"/></mouse-wheel>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let actions: Vec<_> = profiles[1]
+            .bindings
+            .iter()
+            .map(|binding| binding.actions().len())
+            .collect();
+        assert_eq!(actions, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn reject_inheritance_cycles() {
+        let err = parse_profiles(
+            r#"<profiles>
+                <profile name="A" extends="B"/>
+                <profile name="B" extends="A"/>
+            </profiles>"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Profile inheritance cycle: A -> B -> A");
+
+        assert!(parse_profiles(r#"<profiles><profile name="A" extends="C"/></profiles>"#).is_err());
+    }
+
+    #[test]
+    fn merge_profile_files() {
+        let sources = vec![
+            (
+                PathBuf::from("profiles.xml"),
+                r#"<profiles fps="30"><profile name="Games"/></profiles>"#.to_string(),
+            ),
+            (
+                PathBuf::from("profiles.d/wow.xml"),
+                r#"<profiles fps="60">
+                    <hotkeys>
+                        <binding vk_code="0x31"><key vk_code="0x41" delay="3f"/></binding>
+                    </hotkeys>
+                    <profile name="WoW" extends="Games">
+                        <bindings>
+                            <binding vk_code="0x32"><key vk_code="0x41" delay="3f"/></binding>
+                        </bindings>
+                    </profile>
+                </profiles>"#
+                    .to_string(),
+            ),
+        ];
+        let profiles = parse_sources(&sources).unwrap();
+        let names: Vec<_> = profiles.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Games", "WoW"]);
+
+        let delay = |profile: &Profile| profile.bindings[0].actions()[0].delay();
+        assert_eq!(delay(&profiles.hotkeys), Some(Duration::from_millis(50)));
+        assert_eq!(
+            delay(&profiles.profiles[1]),
+            Some(Duration::from_millis(50))
+        );
+
+        let sources = vec![
+            sources[0].clone(),
+            (
+                PathBuf::from("profiles.d/games.xml"),
+                r#"<profiles><profile name="Games"/></profiles>"#.to_string(),
+            ),
+        ];
+        let err = parse_sources(&sources).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Profile Games is defined in both profiles.xml and profiles.d/games.xml"
+        );
+    }
+
+    #[test]
+    fn default_fps_per_file() {
+        let binding = r#"<binding vk_code="0x31"><key vk_code="0x41" delay="3f"/></binding>"#;
+        let sources = vec![
+            (
+                PathBuf::from("profiles.xml"),
+                format!(
+                    r#"<profiles fps="30"><profile name="Games"><bindings>{}</bindings></profile></profiles>"#,
+                    binding
+                ),
+            ),
+            (
+                PathBuf::from("profiles.d/wow.xml"),
+                format!(
+                    r#"<profiles><profile name="WoW"><bindings>{}</bindings></profile></profiles>"#,
+                    binding
+                ),
+            ),
+        ];
+        let profiles = parse_sources(&sources).unwrap().profiles;
+
+        let delays: Vec<_> = profiles
+            .iter()
+            .map(|profile| profile.bindings[0].actions()[0].delay())
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(50))
+            ]
+        );
+    }
+
+    #[test]
+    fn list_profile_files() {
+        let dir = env::temp_dir().join(format!("keymapper-profiles-{}", std::process::id()));
+        fs::create_dir_all(dir.join("profiles.d")).unwrap();
+        for name in &[
+            "profiles.d/b.xml",
+            "profiles.d/a.xml",
+            "profiles.d/notes.txt",
+        ] {
+            fs::write(dir.join(name), "<profiles/>").unwrap();
+        }
+
+        let files = profile_files_in(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            vec![dir.join("profiles.d/a.xml"), dir.join("profiles.d/b.xml")]
+        );
+    }
+
+    #[test]
+    fn write_loaded_profiles() {
+        let text = r#"<profiles>
+            <hotkeys>
+                <binding vk_code="0x13"><toggle-suspend/></binding>
+            </hotkeys>
+            <profile name="Test" fps="30">
+                <triggers><window name="Test"/></triggers>
+                <bindings>
+                    <binding vk_code="0x70" up="true" throttle="200" throttle_mode="sliding" throttle_limit="2">
+                        <key vk_code="0x41" up="false" delay="2.5" jitter="3" jitter_mode="gaussian"/>
+                        <button name="x1"/>
+                        <move x="-10" y="5" absolute="true"/>
+                        <run program="tool.exe" args='-a "b c"' wait="true" timeout="1s"/>
+                        <key vk_code="0x42" delay="2f"/>
+                        <activate-profile name="Test" delay="10"/>
+                    </binding>
+                    <mouse-wheel direction="left" scale="0.5" step="60"/>
+                </bindings>
+            </profile>
+        </profiles>"#;
+
+        let profiles = parse_profiles(text).unwrap();
+        let written = crate::document::to_xml(&write_profiles(&profiles));
+        let reread = parse_profiles(&written).unwrap();
+        assert_eq!(format!("{:?}", reread), format!("{:?}", profiles));
+        assert!(written.contains(r#"<key vk_code="0x41" up="false" delay="2.5" jitter="3""#));
+        assert!(written.contains(r#"<key vk_code="0x42" delay="66.666667"/>"#));
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <var name="step" value="50"/>
+                <var name="double" value="${step}0"/>
+                <profile name="Test">
+                    <var name="step" value="20"/>
+                    <var name="key" value="0x41"/>
+                    <bindings>
+                        <binding vk_code="${key}">
+                            <key vk_code="${key}" delay="${step}ms"/>
+                            <key vk_code="${key}" delay="${double}"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let actions: Vec<_> = profiles[0].bindings[0]
+            .actions()
+            .iter()
+            .map(|action| action.to_string())
+            .collect();
+        assert_eq!(actions, vec!["key 0x41 after 20ms", "key 0x41 after 500ms"]);
+    }
+
+    #[test]
+    fn scope_variables_after_inheritance() {
+        let profiles = parse_profiles(
+            r#"<profiles>
+                <var name="step" value="50"/>
+                <profile name="Base">
+                    <var name="key" value="0x41"/>
+                    <var name="step" value="${step}0"/>
+                    <bindings>
+                        <binding vk_code="0x31"><key vk_code="${key}" delay="${step}"/></binding>
+                    </bindings>
+                </profile>
+                <profile name="Child" extends="Base">
+                    <var name="step" value="${step}5"/>
+                    <bindings>
+                        <binding vk_code="0x32">
+                            <key vk_code="${key}"/>
+                            <run program="echo" args="$${HOME} $$5"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap()
+        .profiles;
+
+        let first =
+            |profile: &Profile, binding: usize| profile.bindings[binding].actions()[0].to_string();
+        assert_eq!(first(&profiles[0], 0), "key 0x41 after 500ms");
+        assert_eq!(first(&profiles[1], 0), "key 0x41 after 5005ms");
+        assert_eq!(first(&profiles[1], 1), "key 0x41");
+        match &profiles[1].bindings[1].actions()[1] {
+            Action::Run(run) => assert_eq!(run.args, vec!["${HOME}", "$$5"]),
+            action => panic!("unexpected {}", action),
+        }
+    }
+
+    #[test]
+    fn report_undefined_variables() {
+        let sources = vec![(
+            PathBuf::from("profiles.d/test.xml"),
+            r#"<profiles>
+                <profile name="Test">
+                    <bindings>
+                        <binding vk_code="0x41"/>
+                        <binding vk_code="0x42"><key vk_code="0x43" delay="${step}"/></binding>
+                    </bindings>
+                </profile>
+            </profiles>"#
+                .to_string(),
+        )];
+
+        let err = parse_sources(&sources).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Undefined variable step in profiles.d/test.xml at \
+             /profiles/profile[@name='Test']/bindings[1]/binding[2]/key[1]/@delay"
+        );
+    }
 }
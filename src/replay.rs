@@ -0,0 +1,381 @@
+//! Recording of input sessions and their replay through the engine.
+//!
+//! Recordings and replay outputs are JSON lines with times in microseconds since the start:
+//!
+//! ```text
+//! {"time":0,"window":"World of Warcraft","event":{"device":"keyboard","vk_code":49,"flags":0,"extra":0}}
+//! {"time":0,"hook":"block","event":{"device":"keyboard","vk_code":49,"flags":0,"extra":0}}
+//! {"time":50000,"output":"key","vk_code":50,"up":false}
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::iter;
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::engine::{Deferred, Engine, EngineState, Environment, MatchedEvent, MACRO_QUEUE_SIZE};
+use crate::errors::AppError;
+use crate::executor::{self, Output};
+use crate::filter::ChatterFilter;
+use crate::profiles::{self, Profiles};
+use crate::settings::Settings;
+use crate::windows::{self, HookAction, InputEvent, Window};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub time: u64,
+    /// Title of the foreground window.
+    pub window: Option<String>,
+    pub event: InputEvent,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ReplayRecord {
+    Hook {
+        time: u64,
+        hook: HookAction,
+        event: InputEvent,
+    },
+    Output {
+        time: u64,
+        #[serde(flatten)]
+        output: Output,
+    },
+}
+
+/// Writes the input seen by the hook to a file. Events are written on a thread of their own,
+/// with the title of the foreground window, so the hook doesn't wait for the file.
+pub struct Recorder {
+    tx: Option<std_mpsc::Sender<(InputEvent, Instant)>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Recorder, AppError> {
+        let file = BufWriter::new(File::create(path)?);
+        let (tx, rx) = std_mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || write_recording(file, rx, Instant::now()))?;
+
+        Ok(Recorder {
+            tx: Some(tx),
+            writer: Some(writer),
+        })
+    }
+
+    /// Records an event of the user, input sent by programs is skipped.
+    pub fn record(&self, e: &InputEvent, now: Instant) {
+        if e.syntetic() {
+            return;
+        }
+        if let Some(tx) = &self.tx {
+            let _ = tx.send((*e, now));
+        }
+    }
+}
+
+impl Drop for Recorder {
+    /// Waits for the events recorded so far to be written.
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_recording(
+    mut file: BufWriter<File>,
+    rx: std_mpsc::Receiver<(InputEvent, Instant)>,
+    started: Instant,
+) {
+    while let Ok(first) = rx.recv() {
+        let window = Window::foreground().map(|w| w.title());
+        let mut result = Ok(());
+        for (event, time) in iter::once(first).chain(rx.try_iter()) {
+            let recorded = RecordedEvent {
+                time: time.saturating_duration_since(started).as_micros() as u64,
+                window: window.clone(),
+                event,
+            };
+            result = result
+                .and_then(|_| serde_json::to_writer(&mut file, &recorded).map_err(io::Error::from))
+                .and_then(|_| file.write_all(b"\n"));
+        }
+
+        // a crash loses no more than the events being written
+        if let Err(err) = result.and_then(|_| file.flush()) {
+            log::error!("Failed to record input: {}", err);
+        }
+    }
+}
+
+/// Foreground window as it was recorded.
+struct RecordedWindow(Arc<Mutex<Option<String>>>);
+
+impl Environment for RecordedWindow {
+    fn is_foreground(&self, window_name: &str) -> bool {
+        self.0.lock().unwrap().as_deref() == Some(window_name)
+    }
+}
+
+/// Feeds recorded events through an engine on a virtual clock, returning what it did.
+/// A new macro aborts the one in progress like it does at runtime. Programs are not run.
+/// Delay jitter uses the configured seed, or 0, so replays are repeatable.
+pub fn replay(
+    profiles: Profiles,
+    settings: &Settings,
+    events: &[RecordedEvent],
+) -> Result<Vec<ReplayRecord>, AppError> {
+    let profiles = Arc::new(profiles);
+    let window = Arc::new(Mutex::new(None));
+    let state = Arc::new(EngineState::default());
+    let (tx, mut rx) = mpsc::channel(MACRO_QUEUE_SIZE);
+    let filter = ChatterFilter::new(&settings.debounce)?;
+    let mut engine = Engine::new(profiles.clone(), state.clone(), filter, tx)
+        .with_environment(RecordedWindow(window.clone()))
+        .without_metrics();
+
+    let mut rng = StdRng::seed_from_u64(settings.macros.seed.unwrap_or(0));
+    let started = Instant::now();
+    let mut records = Vec::new();
+    let mut pending: VecDeque<(u64, Output)> = VecDeque::new();
+
+    // a new macro replaces the outputs still pending
+    let start = |pending: &mut VecDeque<(u64, Output)>,
+                 matched: &MatchedEvent,
+                 mut time: u64,
+                 rng: &mut StdRng| {
+        pending.clear();
+        for step in executor::steps(matched, rng) {
+            time += step.delay.as_micros() as u64;
+            pending.push_back((time, step.output));
+        }
+    };
+
+    let flush = |pending: &mut VecDeque<(u64, Output)>, until: u64, records: &mut Vec<_>| {
+        while pending.front().is_some_and(|(time, _)| *time <= until) {
+            let (time, output) = pending.pop_front().unwrap();
+            if let Output::Control { command } = &output {
+                state.execute(command, &profiles);
+            }
+            records.push(ReplayRecord::Output { time, output });
+        }
+    };
+
+    let mut deferred = Deferred::default();
+    let time_of = |instant: Instant| instant.duration_since(started).as_micros() as u64;
+
+    for recorded in events {
+        let now = started + Duration::from_micros(recorded.time);
+        while let Some(deadline) = deferred.next_deadline().filter(|d| *d <= now) {
+            let matched = deferred.pop(deadline).unwrap();
+            flush(&mut pending, time_of(deadline), &mut records);
+            start(&mut pending, &matched, time_of(deadline), &mut rng);
+        }
+        flush(&mut pending, recorded.time, &mut records);
+
+        *window.lock().unwrap() = recorded.window.clone();
+        let hook = engine.handle(&recorded.event, now);
+        records.push(ReplayRecord::Hook {
+            time: recorded.time,
+            hook,
+            event: recorded.event,
+        });
+
+        while let Ok(matched) = rx.try_recv() {
+            if matched.deadline.is_some() {
+                deferred.push(matched);
+            } else {
+                start(&mut pending, &matched, recorded.time, &mut rng);
+            }
+        }
+    }
+    // deferred macros start after the last event too
+    while let Some(deadline) = deferred.next_deadline() {
+        let matched = deferred.pop(deadline).unwrap();
+        flush(&mut pending, time_of(deadline), &mut records);
+        start(&mut pending, &matched, time_of(deadline), &mut rng);
+    }
+    flush(&mut pending, u64::MAX, &mut records);
+
+    Ok(records)
+}
+
+const USAGE: &str = "Usage: keymapper replay <recording> [<output>] [--expect <file>]";
+
+/// Runs `keymapper replay` and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    match run_replay(args) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn run_replay(args: &[String]) -> Result<(), AppError> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (recording, output, expect) = match args.as_slice() {
+        [recording] => (*recording, None, None),
+        [recording, "--expect", expect] => (*recording, None, Some(*expect)),
+        [recording, output] => (*recording, Some(*output), None),
+        [recording, output, "--expect", expect] => (*recording, Some(*output), Some(*expect)),
+        _ => return Err(AppError::new(USAGE)),
+    };
+
+    let events = BufReader::new(File::open(recording)?)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?).map_err(|e| {
+                AppError::new(format!("{}:{}: invalid event: {}", recording, index + 1, e))
+            })
+        })
+        .collect::<Result<Vec<RecordedEvent>, AppError>>()?;
+
+    let settings = Settings::load()?;
+    let records = replay(profiles::load_profiles()?, &settings, &events)?;
+    let lines = records
+        .iter()
+        .map(|record| serde_json::to_string(record).map_err(|e| AppError::new(e.to_string())))
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    match output {
+        Some(path) => fs::write(path, &text)?,
+        None => io::stdout().write_all(text.as_bytes())?,
+    }
+
+    if let Some(expect) = expect {
+        let expected = fs::read_to_string(expect)?;
+        let expected: Vec<_> = expected.lines().collect();
+        for index in 0..lines.len().max(expected.len()) {
+            let actual = lines.get(index).map(String::as_str);
+            if actual != expected.get(index).copied() {
+                return Err(AppError::new(format!(
+                    "{}:{}: expected {}, got {}",
+                    expect,
+                    index + 1,
+                    expected.get(index).unwrap_or(&"end of replay"),
+                    actual.unwrap_or("end of replay")
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::windows::KeyboardEvent;
+
+    fn key(time: u64, window: &str, vk_code: u32, up: bool) -> RecordedEvent {
+        RecordedEvent {
+            time,
+            window: Some(window.to_string()),
+            event: InputEvent::Keyboard(KeyboardEvent::new(vk_code, up)),
+        }
+    }
+
+    #[test]
+    fn replay_follows_windows_and_aborts_macros() {
+        let profiles = profiles::parse_profiles(
+            r#"<profiles>
+                <profile name="Game">
+                    <triggers><window name="Game"/></triggers>
+                    <bindings>
+                        <binding vk_code="0x31" up="false">
+                            <key vk_code="0x32"/>
+                            <key vk_code="0x33" delay="100"/>
+                        </binding>
+                    </bindings>
+                </profile>
+            </profiles>"#,
+        )
+        .unwrap();
+
+        let events = [
+            key(0, "Notepad", 0x31, false),
+            key(1_000, "Game", 0x31, false),
+            key(51_000, "Game", 0x31, false),
+        ];
+
+        let records = replay(profiles, &Settings::default(), &events).unwrap();
+        let lines: Vec<_> = records
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+
+        let hook = |time, hook| {
+            format!(
+                r#"{{"time":{},"hook":"{}","event":{{"device":"keyboard","vk_code":49,"flags":0,"extra":0}}}}"#,
+                time, hook
+            )
+        };
+        let output = |time, vk_code, up| {
+            format!(
+                r#"{{"time":{},"output":"key","vk_code":{},"up":{}}}"#,
+                time, vk_code, up
+            )
+        };
+
+        assert_eq!(
+            lines,
+            vec![
+                hook(0, "forward"),
+                hook(1_000, "block"),
+                output(1_000, 50, false),
+                hook(51_000, "block"),
+                output(51_000, 50, false),
+                output(151_000, 51, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_events_of_the_user() {
+        let path = std::env::temp_dir().join(format!("keymapper-record-{}", std::process::id()));
+        let recorder = Recorder::create(path.to_str().unwrap()).unwrap();
+        let sent = KeyboardEvent {
+            extra: 1,
+            ..KeyboardEvent::new(0x42, false)
+        };
+        recorder.record(&InputEvent::Keyboard(sent), Instant::now());
+        recorder.record(
+            &InputEvent::Keyboard(KeyboardEvent::new(0x41, false)),
+            Instant::now(),
+        );
+        drop(recorder);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let events: Vec<RecordedEvent> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 1);
+        match events[0].event {
+            InputEvent::Keyboard(e) => assert_eq!(e.vk_code, 0x41),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::future;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::process::{Child, Command};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio::time;
+
+use crate::engine::EngineState;
+use crate::executor::{Output, Step};
+use crate::metrics::METRICS;
+use crate::profiles::{Profiles, Run};
+use crate::windows;
+
+/// Deadlines closer than this are waited for by spinning, as sleeps overshoot by up to a tick.
+const SPIN_WINDOW: Duration = Duration::from_millis(2);
+
+/// A macro to run: its steps and where they came from.
+pub struct Job {
+    pub profiles: Arc<Profiles>,
+    pub steps: Vec<Step>,
+    pub source: String,
+}
+
+enum Message {
+    Job(Job),
+    /// A program has exited, with the id `start_program` gave it.
+    ProgramExited(u64),
+}
+
+/// Runs macros on a dedicated thread, one at a time: a new job aborts the one in progress.
+pub struct Scheduler {
+    tx: Sender<Message>,
+}
+
+impl Scheduler {
+    /// Programs are run on the given runtime.
+    pub fn start(state: Arc<EngineState>, runtime: Handle) -> Scheduler {
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker {
+            rx,
+            tx: tx.clone(),
+            state,
+            runtime,
+            program_id: 0,
+        };
+
+        thread::Builder::new()
+            .name("macro-scheduler".to_string())
+            .spawn(move || worker.run())
+            .expect("Failed to start macro scheduler.");
+
+        Scheduler { tx }
+    }
+
+    pub fn submit(&self, job: Job) {
+        if self.tx.send(Message::Job(job)).is_err() {
+            log::error!("Macro scheduler has stopped.");
+        }
+    }
+}
+
+struct Worker {
+    rx: Receiver<Message>,
+    tx: Sender<Message>,
+    state: Arc<EngineState>,
+    runtime: Handle,
+    /// Id of the last program started.
+    program_id: u64,
+}
+
+impl Worker {
+    fn run(mut self) {
+        windows::set_timer_resolution(1);
+
+        let mut next = None;
+        loop {
+            let job = match next.take() {
+                Some(job) => job,
+                None => match self.rx.recv() {
+                    Ok(Message::Job(job)) => job,
+                    Ok(Message::ProgramExited(_)) => continue,
+                    Err(_) => return,
+                },
+            };
+            next = self.run_job(job);
+        }
+    }
+
+    /// Runs the steps against a timeline starting now. Returns the job that aborted it, if any.
+    fn run_job(&mut self, job: Job) -> Option<Job> {
+        let started = Instant::now();
+        let mut deadline = started;
+        let mut max_error = Duration::default();
+
+        for (index, step) in job.steps.iter().enumerate() {
+            deadline += step.delay;
+            if let Some(next) = self.wait_until(deadline) {
+                log::trace!("{} aborted at step {}", job.source, index + 1);
+                return Some(next);
+            }
+
+            let error = Instant::now().saturating_duration_since(deadline);
+            METRICS.step_error(error);
+            max_error = max_error.max(error);
+            log::trace!("{} step {} late by {:?}", job.source, index + 1, error);
+
+            match &step.output {
+                Output::Run(run) => {
+                    let program = self.start_program(run, &job.source);
+                    if let (true, Some((id, kill))) = (run.wait, program) {
+                        if let Some(next) = self.wait_program(id) {
+                            log::trace!("{} aborted waiting for {}", job.source, run.program);
+                            // the program ends with the macro that waits for it
+                            let _ = kill.send(());
+                            return Some(next);
+                        }
+                        // the timeline continues from the exit of the program
+                        deadline = Instant::now();
+                    }
+                }
+                output => self.perform(output, &job),
+            }
+        }
+
+        METRICS.macro_finished(started.elapsed());
+        log::debug!(
+            "{} finished in {:?}, steps late by up to {:?}",
+            job.source,
+            started.elapsed(),
+            max_error
+        );
+        None
+    }
+
+    /// Sleeps until shortly before the deadline, then spins. Returns a new job if one arrives.
+    fn wait_until(&self, deadline: Instant) -> Option<Job> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+
+            let remaining = deadline - now;
+            let message = if remaining > SPIN_WINDOW {
+                match self.rx.recv_timeout(remaining - SPIN_WINDOW) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            } else {
+                match self.rx.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                        std::hint::spin_loop();
+                        continue;
+                    }
+                }
+            };
+
+            if let Message::Job(job) = message {
+                return Some(job);
+            }
+        }
+    }
+
+    /// Waits for the program with the id to exit. Returns a new job if one arrives.
+    fn wait_program(&self, id: u64) -> Option<Job> {
+        loop {
+            match self.rx.recv() {
+                Ok(Message::Job(job)) => return Some(job),
+                Ok(Message::ProgramExited(exited)) if exited == id => return None,
+                Ok(Message::ProgramExited(_)) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Starts the program on the runtime. Returns its id and a sender that kills it, or None if
+    /// it failed to start.
+    fn start_program(&mut self, run: &Run, source: &str) -> Option<(u64, oneshot::Sender<()>)> {
+        log::trace!("Running: {} {:?}", run.program, run.args);
+
+        let mut command = Command::new(&run.program);
+        command.args(&run.args);
+        if let Some(working_dir) = &run.working_dir {
+            command.current_dir(working_dir);
+        }
+
+        // spawning registers the child with the runtime
+        let _guard = self.runtime.enter();
+        let child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                log::error!("{} failed to run {}: {}", source, run.program, err);
+                return None;
+            }
+        };
+
+        self.program_id += 1;
+        let id = self.program_id;
+        let tx = self.tx.clone();
+        let run = run.clone();
+        let source = source.to_string();
+        let (kill, killed) = oneshot::channel();
+        self.runtime.spawn(async move {
+            wait_program(child, run, source, killed).await;
+            let _ = tx.send(Message::ProgramExited(id));
+        });
+        Some((id, kill))
+    }
+
+    fn perform(&self, output: &Output, job: &Job) {
+        match output {
+            Output::Key { vk_code, up } => {
+                log::trace!("Sending key: {:X}, up = {:?}", vk_code, up);
+                windows::send_input_key(*vk_code as i32, *up);
+            }
+            Output::Button { button, up } => {
+                log::trace!("Sending mouse button: {:?}, up = {:?}", button, up);
+                windows::send_input_mouse_button(*button, *up);
+            }
+            Output::Move { x, y, absolute } => {
+                log::trace!("Moving mouse: {}, {}, absolute = {:?}", x, y, absolute);
+                windows::send_input_mouse_move(*x, *y, *absolute);
+            }
+            Output::Scroll { delta, horizontal } => {
+                log::trace!("Scrolling mouse: {}, horizontal = {:?}", delta, horizontal);
+                windows::send_input_mouse_wheel(*delta, *horizontal);
+            }
+            Output::Run(_) => {}
+            Output::Control { command } => {
+                log::trace!("{} runs {:?}", job.source, command);
+                self.state.execute(command, &job.profiles);
+            }
+        }
+    }
+}
+
+/// Waits for the program to exit, killing it on timeout or when `killed` receives.
+async fn wait_program(mut child: Child, run: Run, source: String, killed: oneshot::Receiver<()>) {
+    let expired = async {
+        match run.timeout {
+            Some(duration) => time::sleep(duration).await,
+            None => future::pending().await,
+        }
+    };
+
+    let status = tokio::select! {
+        status = child.wait() => Some(status),
+        _ = expired => {
+            log::error!("{} killed {} after {:?}", source, run.program, run.timeout.unwrap());
+            None
+        }
+        Ok(()) = killed => {
+            log::debug!("{} was aborted, killing {}", source, run.program);
+            None
+        }
+    };
+
+    let status = match status {
+        Some(status) => status,
+        None => {
+            if let Err(err) = child.kill().await {
+                log::error!("{} failed to kill {}: {}", source, run.program, err);
+            }
+            return;
+        }
+    };
+
+    match status {
+        Ok(status) if status.success() => log::trace!("{} exited", run.program),
+        Ok(status) => log::error!(
+            "{} ran {} which exited with {}",
+            source,
+            run.program,
+            status
+        ),
+        Err(err) => log::error!("{} failed to wait for {}: {}", source, run.program, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::parse_profiles;
+
+    fn job(profiles: &Arc<Profiles>, delays: &[u64]) -> Job {
+        Job {
+            profiles: profiles.clone(),
+            steps: delays
+                .iter()
+                .map(|ms| Step {
+                    delay: Duration::from_millis(*ms),
+                    output: Output::Control {
+                        command: crate::profiles::ControlCommand::ToggleSuspend,
+                    },
+                })
+                .collect(),
+            source: "Test".to_string(),
+        }
+    }
+
+    #[test]
+    fn run_steps_in_order_and_abort_on_new_job() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let profiles = Arc::new(parse_profiles("<profiles/>").unwrap());
+        let state = Arc::new(EngineState::default());
+        let scheduler = Scheduler::start(state.clone(), runtime.handle().clone());
+        let mut events = state.subscribe();
+        let mut next_toggle = || {
+            runtime
+                .block_on(async { time::timeout(Duration::from_secs(5), events.recv()).await })
+                .expect("toggle in time")
+                .unwrap()
+        };
+
+        scheduler.submit(job(&profiles, &[0, 10, 60_000]));
+        next_toggle();
+        next_toggle();
+        // only aborting the last step of the first job lets the second one run in time
+        scheduler.submit(job(&profiles, &[5]));
+        next_toggle();
+
+        assert!(state.is_suspended());
+    }
+
+    fn run(program: &str, args: &[&str], wait: bool) -> Step {
+        Step {
+            delay: Duration::default(),
+            output: Output::Run(Run {
+                program: program.to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                working_dir: None,
+                wait,
+                timeout: None,
+                delay: None,
+            }),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wait_for_own_program_only() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let profiles = Arc::new(parse_profiles("<profiles/>").unwrap());
+        let state = Arc::new(EngineState::default());
+        let scheduler = Scheduler::start(state.clone(), runtime.handle().clone());
+        let mut events = state.subscribe();
+
+        let mut steps = vec![run("true", &[], false), run("sleep", &["0.3"], true)];
+        steps.extend(job(&profiles, &[0]).steps);
+        let started = Instant::now();
+        scheduler.submit(Job {
+            profiles: profiles.clone(),
+            steps,
+            source: "Test".to_string(),
+        });
+
+        // the detached program exits first, the macro goes on when the waited one does
+        runtime
+            .block_on(async { time::timeout(Duration::from_secs(5), events.recv()).await })
+            .expect("toggle in time")
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kill_waited_program_on_abort() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let profiles = Arc::new(parse_profiles("<profiles/>").unwrap());
+        let state = Arc::new(EngineState::default());
+        let scheduler = Scheduler::start(state, runtime.handle().clone());
+
+        let pid_file = std::env::temp_dir().join(format!("keymapper-kill-{}", std::process::id()));
+        let _ = std::fs::remove_file(&pid_file);
+        let script = format!("echo $$ > {}; exec sleep 60", pid_file.display());
+        scheduler.submit(Job {
+            profiles: profiles.clone(),
+            steps: vec![run("sh", &["-c", &script], true)],
+            source: "Test".to_string(),
+        });
+
+        let within = |condition: &dyn Fn() -> bool| {
+            let started = Instant::now();
+            while !condition() {
+                assert!(started.elapsed() < Duration::from_secs(5));
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        let pid = || std::fs::read_to_string(&pid_file).unwrap_or_default();
+        within(&|| pid().ends_with('\n'));
+        let running = || {
+            std::process::Command::new("kill")
+                .args(["-0", pid().trim()])
+                .stderr(std::process::Stdio::null())
+                .status()
+                .unwrap()
+                .success()
+        };
+        assert!(running());
+
+        scheduler.submit(job(&profiles, &[0]));
+        within(&|| !running());
+        let _ = std::fs::remove_file(&pid_file);
+    }
+}
@@ -0,0 +1,1079 @@
+//! Description of every element and attribute of the profiles document, published as an XSD
+//! for XML editors and a JSON Schema for the TOML and YAML formats.
+
+use serde_json::{json, Map, Value};
+use xml::*;
+
+use crate::errors::AppError;
+use crate::keys;
+use crate::profiles;
+use crate::windows;
+
+const USAGE: &str = "Usage: keymapper schema <xsd|json>";
+
+const HEX_PATTERN: &str = "0[xX][0-9A-Fa-f]+";
+/// The grammar of `profiles::parse_duration`, spaces around the unit included.
+const DURATION_PATTERN: &str = "[ \\t\\n\\r]*[0-9]+(\\.[0-9]+)?[ \\t\\n\\r]*(ms|s|f)?[ \\t\\n\\r]*";
+const VARIABLE_PATTERN: &str = "\\$\\{[A-Za-z_][A-Za-z0-9_.\\-]*\\}";
+
+/// Value of an attribute.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    /// Hex code or key name.
+    KeyCode,
+    Bool,
+    Integer,
+    Number,
+    /// Milliseconds, or with a `ms`, `s` or `f` unit.
+    Duration,
+    Choice(&'static [&'static str]),
+}
+
+struct Attribute {
+    name: &'static str,
+    kind: Kind,
+    required: bool,
+    description: &'static str,
+}
+
+/// An element in one place of the document. Names can repeat with different shapes, like
+/// macro definitions and macro actions, so each has its own id.
+struct ElementSpec {
+    id: &'static str,
+    name: &'static str,
+    description: &'static str,
+    attributes: Vec<Attribute>,
+    children: &'static [&'static str],
+}
+
+const ROOT: &str = "profiles";
+
+/// Elements holding a list in the TOML and YAML formats rather than being one.
+const SECTIONS: &[&str] = &["macros", "hotkeys", "triggers", "bindings"];
+
+const BINDINGS: &[&str] = &["binding", "mouse-button", "mouse-wheel"];
+
+const ACTIONS: &[&str] = &[
+    "key",
+    "button",
+    "move",
+    "scroll",
+    "run",
+    "suspend",
+    "resume",
+    "toggle-suspend",
+    "activate-profile",
+    "cycle-profiles",
+    "motion",
+    "macro",
+];
+
+fn attribute(name: &'static str, kind: Kind, description: &'static str) -> Attribute {
+    Attribute {
+        name,
+        kind,
+        required: false,
+        description,
+    }
+}
+
+fn required(name: &'static str, kind: Kind, description: &'static str) -> Attribute {
+    Attribute {
+        required: true,
+        ..attribute(name, kind, description)
+    }
+}
+
+fn element(
+    id: &'static str,
+    description: &'static str,
+    attributes: Vec<Attribute>,
+    children: &'static [&'static str],
+) -> ElementSpec {
+    ElementSpec {
+        id,
+        name: id,
+        description,
+        attributes,
+        children,
+    }
+}
+
+fn delay() -> Attribute {
+    attribute("delay", Kind::Duration, "Wait before this action.")
+}
+
+fn up(description: &'static str) -> Attribute {
+    attribute("up", Kind::Bool, description)
+}
+
+fn binding_attributes(mut attributes: Vec<Attribute>) -> Vec<Attribute> {
+    attributes.insert(
+        0,
+        attribute(
+            "name",
+            Kind::Text,
+            "Name used in logs, metrics and record-macro --insert.",
+        ),
+    );
+    attributes.extend(vec![
+        attribute("throttle", Kind::Duration, "Rate limiting window."),
+        attribute(
+            "throttle_mode",
+            Kind::Choice(&["leading", "trailing", "sliding"]),
+            "How events within the throttle window are handled, leading by default.",
+        ),
+        attribute(
+            "throttle_limit",
+            Kind::Integer,
+            "Events allowed within a sliding window.",
+        ),
+        attribute(
+            "jitter",
+            Kind::Duration,
+            "Random offset range of the action delays.",
+        ),
+        attribute(
+            "jitter_mode",
+            Kind::Choice(&["uniform", "gaussian"]),
+            "Distribution of the jitter, uniform by default.",
+        ),
+    ]);
+    attributes
+}
+
+fn timing() -> Vec<Attribute> {
+    vec![
+        attribute(
+            "hold",
+            Kind::Duration,
+            "Time a key is held, 50ms by default.",
+        ),
+        attribute(
+            "gap",
+            Kind::Duration,
+            "Time between releasing a key and the next press, 50ms by default.",
+        ),
+    ]
+}
+
+fn spec() -> Vec<ElementSpec> {
+    let fps = || {
+        attribute(
+            "fps",
+            Kind::Number,
+            "Frame rate of durations in frames, 60 by default.",
+        )
+    };
+    let control = |id, description| element(id, description, vec![delay()], &[]);
+
+    let mut macro_attributes = vec![
+        attribute(
+            "keys",
+            Kind::Text,
+            "Chords of key names joined with +, separated by commas, and waits.",
+        ),
+        delay(),
+    ];
+    macro_attributes.extend(timing());
+    let mut definition_attributes = vec![required(
+        "id",
+        Kind::Text,
+        "Name that macro references use.",
+    )];
+    definition_attributes.extend(timing());
+    definition_attributes.push(attribute(
+        "keys",
+        Kind::Text,
+        "Macro string used instead of child actions.",
+    ));
+    let mut motion_attributes = vec![
+        required("notation", Kind::Text, "Numpad notation, like 2 3 6 + LP."),
+        attribute(
+            "facing",
+            Kind::Choice(&["right", "left"]),
+            "Side the character faces, left mirrors the directions.",
+        ),
+        delay(),
+    ];
+    motion_attributes.extend(timing());
+    let mut motion_input_attributes = vec![
+        attribute("up", Kind::KeyCode, "Key of direction 8."),
+        attribute("down", Kind::KeyCode, "Key of direction 2."),
+        attribute("left", Kind::KeyCode, "Key of direction 4."),
+        attribute("right", Kind::KeyCode, "Key of direction 6."),
+    ];
+    motion_input_attributes.extend(timing());
+
+    vec![
+        element(
+            ROOT,
+            "Profiles document.",
+            vec![
+                attribute(
+                    "version",
+                    Kind::Integer,
+                    "Document format version, 1 when missing.",
+                ),
+                fps(),
+            ],
+            &["var", "macros", "hotkeys", "profile"],
+        ),
+        element(
+            "var",
+            "Variable usable in attributes as ${name}.",
+            vec![
+                required("name", Kind::Text, "Variable name."),
+                required(
+                    "value",
+                    Kind::Text,
+                    "Replaces ${name}, can use variables defined before.",
+                ),
+            ],
+            &[],
+        ),
+        element(
+            "macros",
+            "Macros that bindings and other macros can reference.",
+            vec![],
+            &["macro-definition"],
+        ),
+        ElementSpec {
+            name: "macro",
+            ..element(
+                "macro-definition",
+                "Named sequence of actions.",
+                definition_attributes,
+                ACTIONS,
+            )
+        },
+        element(
+            "hotkeys",
+            "Bindings checked before any profile, even while suspended.",
+            vec![],
+            BINDINGS,
+        ),
+        element(
+            "profile",
+            "Bindings active while one of the triggers matches.",
+            vec![
+                required("name", Kind::Text, "Unique profile name."),
+                attribute(
+                    "extends",
+                    Kind::Text,
+                    "Profile whose triggers and bindings this one inherits.",
+                ),
+                fps(),
+            ],
+            &["var", "triggers", "motion-input", "bindings"],
+        ),
+        element(
+            "triggers",
+            "The profile is active while any of these matches.",
+            vec![],
+            &["window"],
+        ),
+        element(
+            "window",
+            "Matches while a window with this title is in the foreground.",
+            vec![required("name", Kind::Text, "Window title.")],
+            &[],
+        ),
+        element(
+            "motion-input",
+            "Keys that motions of the profile are written in, facing right.",
+            motion_input_attributes,
+            &["token"],
+        ),
+        element(
+            "token",
+            "Button of a motion, like LP.",
+            vec![
+                required("name", Kind::Text, "Token used in the notation."),
+                required("vk_code", Kind::KeyCode, "Key of the token."),
+            ],
+            &[],
+        ),
+        element("bindings", "Inputs the profile handles.", vec![], BINDINGS),
+        element(
+            "binding",
+            "Keyboard key binding, without actions it blocks the key.",
+            binding_attributes(vec![
+                required("vk_code", Kind::KeyCode, "Key to bind."),
+                up("Matches only releases when true, only presses when false."),
+                attribute(
+                    "alt",
+                    Kind::Bool,
+                    "Matches only with Alt held, or not held.",
+                ),
+            ]),
+            ACTIONS,
+        ),
+        element(
+            "mouse-button",
+            "Mouse button binding.",
+            binding_attributes(vec![
+                required(
+                    "button",
+                    Kind::Choice(&["left", "right", "middle", "x1", "x2"]),
+                    "Button to bind.",
+                ),
+                up("Matches only releases when true, only presses when false."),
+            ]),
+            ACTIONS,
+        ),
+        element(
+            "mouse-wheel",
+            "Mouse wheel binding.",
+            binding_attributes(vec![
+                attribute(
+                    "direction",
+                    Kind::Choice(&["up", "down", "left", "right"]),
+                    "Direction to bind, both ways of the vertical wheel when missing.",
+                ),
+                up("Matches wheel up (or right) when true, down (or left) when false."),
+                attribute("horizontal", Kind::Bool, "Binds the tilt wheel."),
+                attribute("scale", Kind::Number, "Multiplier of the wheel deltas."),
+                attribute(
+                    "step",
+                    Kind::Integer,
+                    "Accumulated delta that fires the actions once, 120 by default.",
+                ),
+            ]),
+            ACTIONS,
+        ),
+        element(
+            "key",
+            "Presses or releases a key, or both when up is missing.",
+            vec![
+                required("vk_code", Kind::KeyCode, "Key to send."),
+                up("Sends only the release when true, only the press when false."),
+                delay(),
+                attribute(
+                    "jitter",
+                    Kind::Duration,
+                    "Random offset range of the delay.",
+                ),
+                attribute(
+                    "jitter_mode",
+                    Kind::Choice(&["uniform", "gaussian"]),
+                    "Distribution of the jitter.",
+                ),
+            ],
+            &[],
+        ),
+        element(
+            "button",
+            "Clicks, presses or releases a mouse button.",
+            vec![
+                required(
+                    "name",
+                    Kind::Choice(&["left", "right", "middle", "x1", "x2"]),
+                    "Button to send.",
+                ),
+                up("Sends only the release when true, only the press when false."),
+                delay(),
+            ],
+            &[],
+        ),
+        element(
+            "move",
+            "Moves the pointer.",
+            vec![
+                attribute("x", Kind::Integer, "Horizontal distance or position."),
+                attribute("y", Kind::Integer, "Vertical distance or position."),
+                attribute(
+                    "absolute",
+                    Kind::Bool,
+                    "Moves to the position instead of by the distance.",
+                ),
+                delay(),
+            ],
+            &[],
+        ),
+        element(
+            "scroll",
+            "Turns the mouse wheel.",
+            vec![
+                required(
+                    "delta",
+                    Kind::Integer,
+                    "Wheel delta, 120 per notch, negative scrolls down or left.",
+                ),
+                attribute("horizontal", Kind::Bool, "Turns the tilt wheel."),
+                delay(),
+            ],
+            &[],
+        ),
+        element(
+            "run",
+            "Starts a program.",
+            vec![
+                required("program", Kind::Text, "Program to start."),
+                attribute(
+                    "args",
+                    Kind::Text,
+                    "Arguments separated by spaces, double quotes keep spaces.",
+                ),
+                attribute("working_dir", Kind::Text, "Directory to start in."),
+                attribute(
+                    "wait",
+                    Kind::Bool,
+                    "Waits for the program to exit before the next action.",
+                ),
+                attribute(
+                    "timeout",
+                    Kind::Duration,
+                    "Kills the program if it is still running after this time.",
+                ),
+                delay(),
+            ],
+            &[],
+        ),
+        control("suspend", "Suspends all profiles."),
+        control("resume", "Resumes all profiles."),
+        control("toggle-suspend", "Suspends or resumes all profiles."),
+        element(
+            "activate-profile",
+            "Keeps a profile active regardless of its triggers.",
+            vec![
+                attribute(
+                    "name",
+                    Kind::Text,
+                    "Profile to activate, triggers apply again when missing.",
+                ),
+                delay(),
+            ],
+            &[],
+        ),
+        control(
+            "cycle-profiles",
+            "Activates the next profile, or none after the last one.",
+        ),
+        element(
+            "motion",
+            "Fighting game motion in numpad notation, keys from the motion-input.",
+            motion_attributes,
+            &[],
+        ),
+        element(
+            "macro",
+            "Macro string, or a reference to a macro definition.",
+            {
+                macro_attributes.insert(
+                    0,
+                    attribute("ref", Kind::Text, "Id of the macro definition to run."),
+                );
+                macro_attributes
+            },
+            &[],
+        ),
+    ]
+}
+
+fn find<'a>(spec: &'a [ElementSpec], id: &str) -> &'a ElementSpec {
+    spec.iter()
+        .find(|e| e.id == id)
+        .unwrap_or_else(|| panic!("Element {} is missing from the schema", id))
+}
+
+/// XML Schema of profiles documents.
+pub fn xsd() -> String {
+    let spec = spec();
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<xs:schema xmlns:xs=\"http://www.w3.org/2001/XMLSchema\">\n");
+    out.push_str(&format!(
+        "    <xs:element name=\"{}\" type=\"{}-type\"/>\n",
+        ROOT, ROOT
+    ));
+
+    for e in &spec {
+        out.push_str(&format!("    <xs:complexType name=\"{}-type\">\n", e.id));
+        xsd_documentation(&mut out, 2, e.description);
+        if !e.children.is_empty() {
+            out.push_str("        <xs:choice minOccurs=\"0\" maxOccurs=\"unbounded\">\n");
+            for child in e.children {
+                let child = find(&spec, child);
+                out.push_str(&format!(
+                    "            <xs:element name=\"{}\" type=\"{}-type\"/>\n",
+                    child.name, child.id
+                ));
+            }
+            out.push_str("        </xs:choice>\n");
+        }
+        for a in &e.attributes {
+            let kind = match a.kind {
+                Kind::Text => "xs:string",
+                Kind::KeyCode => "key-code",
+                Kind::Bool => "boolean",
+                Kind::Integer => "integer",
+                Kind::Number => "number",
+                Kind::Duration => "duration",
+                Kind::Choice(_) => "",
+            };
+            let kind = match kind {
+                "" => String::new(),
+                kind => format!(" type=\"{}\"", kind),
+            };
+            let usage = if a.required { " use=\"required\"" } else { "" };
+            out.push_str(&format!(
+                "        <xs:attribute name=\"{}\"{}{}>\n",
+                a.name, kind, usage
+            ));
+            xsd_documentation(&mut out, 3, a.description);
+            if let Kind::Choice(values) = a.kind {
+                out.push_str("            <xs:simpleType>\n");
+                out.push_str("                <xs:union memberTypes=\"variable\">\n");
+                xsd_enumeration(&mut out, 5, "xs:string", values.iter().copied());
+                out.push_str("                </xs:union>\n");
+                out.push_str("            </xs:simpleType>\n");
+            }
+            out.push_str("        </xs:attribute>\n");
+        }
+        out.push_str("    </xs:complexType>\n");
+    }
+
+    out.push_str("    <xs:simpleType name=\"key-code\">\n");
+    xsd_documentation(&mut out, 2, KEY_CODE_DESCRIPTION);
+    out.push_str("        <xs:union memberTypes=\"variable\">\n");
+    xsd_pattern(&mut out, 3, HEX_PATTERN);
+    let names = keys::names();
+    xsd_enumeration(&mut out, 3, "xs:string", names.iter().map(String::as_str));
+    xsd_pattern(&mut out, 3, &key_name_pattern());
+    out.push_str("        </xs:union>\n");
+    out.push_str("    </xs:simpleType>\n");
+
+    out.push_str("    <xs:simpleType name=\"boolean\">\n");
+    out.push_str("        <xs:union memberTypes=\"variable\">\n");
+    xsd_enumeration(&mut out, 3, "xs:string", ["true", "false"].iter().copied());
+    out.push_str("        </xs:union>\n");
+    out.push_str("    </xs:simpleType>\n");
+
+    out.push_str("    <xs:simpleType name=\"integer\">\n");
+    out.push_str("        <xs:union memberTypes=\"xs:integer variable\"/>\n");
+    out.push_str("    </xs:simpleType>\n");
+
+    out.push_str("    <xs:simpleType name=\"number\">\n");
+    out.push_str("        <xs:union memberTypes=\"xs:decimal variable\"/>\n");
+    out.push_str("    </xs:simpleType>\n");
+
+    out.push_str("    <xs:simpleType name=\"duration\">\n");
+    xsd_documentation(&mut out, 2, "Milliseconds, or with a unit: ms, s or f.");
+    out.push_str("        <xs:union memberTypes=\"variable\">\n");
+    xsd_pattern(&mut out, 3, DURATION_PATTERN);
+    out.push_str("        </xs:union>\n");
+    out.push_str("    </xs:simpleType>\n");
+
+    out.push_str("    <xs:simpleType name=\"variable\">\n");
+    xsd_documentation(
+        &mut out,
+        2,
+        "Text using variables, replaced when profiles load.",
+    );
+    out.push_str("        <xs:restriction base=\"xs:string\">\n");
+    out.push_str(&format!(
+        "            <xs:pattern value=\".*{}.*\"/>\n",
+        VARIABLE_PATTERN
+    ));
+    out.push_str("        </xs:restriction>\n");
+    out.push_str("    </xs:simpleType>\n");
+
+    out.push_str("</xs:schema>\n");
+    out
+}
+
+fn xsd_documentation(out: &mut String, depth: usize, text: &str) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!(
+        "{0}<xs:annotation>\n{0}    <xs:documentation>{1}</xs:documentation>\n{0}</xs:annotation>\n",
+        indent,
+        escape(text)
+    ));
+}
+
+fn xsd_pattern(out: &mut String, depth: usize, pattern: &str) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!(
+        "{0}<xs:simpleType>\n{0}    <xs:restriction base=\"xs:string\">\n{0}        <xs:pattern value=\"{1}\"/>\n{0}    </xs:restriction>\n{0}</xs:simpleType>\n",
+        indent, pattern
+    ));
+}
+
+fn xsd_enumeration<'a, I: Iterator<Item = &'a str>>(
+    out: &mut String,
+    depth: usize,
+    base: &str,
+    values: I,
+) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!(
+        "{0}<xs:simpleType>\n{0}    <xs:restriction base=\"{1}\">\n",
+        indent, base
+    ));
+    for value in values {
+        out.push_str(&format!(
+            "{}        <xs:enumeration value=\"{}\"/>\n",
+            indent,
+            escape(value)
+        ));
+    }
+    out.push_str(&format!(
+        "{0}    </xs:restriction>\n{0}</xs:simpleType>\n",
+        indent
+    ));
+}
+
+/// Key codes are read ignoring case, the enumeration of names only offers one spelling of
+/// each to editors.
+const KEY_CODE_DESCRIPTION: &str =
+    "Virtual key code in hex, or a key name. Names are matched ignoring case.";
+
+/// Matches the key names in any case, like `[Tt][Aa][Bb]`.
+fn key_name_pattern() -> String {
+    let names: Vec<String> = keys::names()
+        .iter()
+        .map(|name| {
+            name.chars()
+                .map(|c| match c.is_ascii_alphabetic() {
+                    true => format!("[{}{}]", c.to_ascii_uppercase(), c.to_ascii_lowercase()),
+                    false => c.to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    format!("({})", names.join("|"))
+}
+
+/// JSON Schema of profiles documents in the TOML and YAML formats.
+pub fn json_schema() -> Value {
+    let spec = spec();
+    let mut definitions = Map::new();
+
+    for e in &spec {
+        if SECTIONS.contains(&e.id) || e.id == ROOT || e.id == "var" {
+            continue;
+        }
+        definitions.insert(e.id.to_string(), json_element(&spec, e));
+    }
+
+    let variable = json!({ "$ref": "#/$defs/variable" });
+    definitions.insert(
+        "key-code".to_string(),
+        json!({
+            "description": KEY_CODE_DESCRIPTION,
+            "anyOf": [
+                { "type": "string", "pattern": format!("^{}$", HEX_PATTERN) },
+                { "enum": keys::names() },
+                { "type": "string", "pattern": format!("^{}$", key_name_pattern()) },
+                variable
+            ]
+        }),
+    );
+    definitions.insert(
+        "boolean".to_string(),
+        json!({ "anyOf": [{ "type": "boolean" }, { "enum": ["true", "false"] }, variable] }),
+    );
+    definitions.insert(
+        "integer".to_string(),
+        json!({
+            "anyOf": [
+                { "type": "integer" },
+                { "type": "string", "pattern": "^-?[0-9]+$" },
+                variable
+            ]
+        }),
+    );
+    definitions.insert(
+        "number".to_string(),
+        json!({
+            "anyOf": [
+                { "type": "number" },
+                { "type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+)?$" },
+                variable
+            ]
+        }),
+    );
+    definitions.insert(
+        "duration".to_string(),
+        json!({
+            "description": "Milliseconds, or with a unit: ms, s or f.",
+            "anyOf": [
+                { "type": "number", "minimum": 0 },
+                { "type": "string", "pattern": format!("^{}$", DURATION_PATTERN) },
+                variable
+            ]
+        }),
+    );
+    definitions.insert(
+        "variable".to_string(),
+        json!({
+            "description": "Text using variables, replaced when profiles load.",
+            "type": "string",
+            "pattern": VARIABLE_PATTERN
+        }),
+    );
+    definitions.insert(
+        "var".to_string(),
+        json!({
+            "description": "Variable usable in attributes as ${name}.",
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "value": { "type": ["string", "number", "boolean"] },
+                "comment": { "$ref": "#/$defs/comment" }
+            },
+            "required": ["name", "value"],
+            "additionalProperties": false
+        }),
+    );
+    definitions.insert(
+        "comment".to_string(),
+        json!({ "description": "XML comment before the element.", "type": "string" }),
+    );
+    definitions.insert(
+        "comment-node".to_string(),
+        json!({
+            "description": "XML comment with no element after it.",
+            "type": "object",
+            "properties": { "comment": { "$ref": "#/$defs/comment" } },
+            "required": ["comment"],
+            "additionalProperties": false
+        }),
+    );
+
+    let mut schema = json_element(&spec, find(&spec, ROOT));
+    let schema = schema.as_object_mut().unwrap();
+    schema.insert(
+        "$schema".to_string(),
+        json!("https://json-schema.org/draft/2020-12/schema"),
+    );
+    schema.insert("title".to_string(), json!("keymapper profiles"));
+    schema.insert("$defs".to_string(), Value::Object(definitions));
+    Value::Object(schema.clone())
+}
+
+/// The root and profiles hold their sections by name, other elements are tables with their
+/// name in `type` and their child elements in `children`.
+fn json_element(spec: &[ElementSpec], e: &ElementSpec) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![];
+    let flattened = e.id == ROOT || e.id == "profile";
+    let variable = json!({ "$ref": "#/$defs/variable" });
+
+    if !flattened {
+        properties.insert("type".to_string(), json!({ "const": e.name }));
+        required.push(json!("type"));
+    }
+    if e.id != ROOT {
+        properties.insert("comment".to_string(), json!({ "$ref": "#/$defs/comment" }));
+    }
+
+    for a in &e.attributes {
+        let mut property = match a.kind {
+            Kind::Text => json!({ "type": "string" }),
+            Kind::KeyCode => json!({ "$ref": "#/$defs/key-code" }),
+            Kind::Bool => json!({ "$ref": "#/$defs/boolean" }),
+            Kind::Integer => json!({ "$ref": "#/$defs/integer" }),
+            Kind::Number => json!({ "$ref": "#/$defs/number" }),
+            Kind::Duration => json!({ "$ref": "#/$defs/duration" }),
+            Kind::Choice(values) => json!({ "anyOf": [{ "enum": values }, variable] }),
+        };
+        property["description"] = json!(a.description);
+        properties.insert(a.name.to_string(), property);
+        if a.required {
+            required.push(json!(a.name));
+        }
+    }
+
+    // lists of elements can also hold comments of their own
+    let one_of = |children: &[&str], comments: bool| {
+        let refs: Vec<_> = children
+            .iter()
+            .chain(Some(&"comment-node").filter(|_| comments))
+            .map(|child| json!({ "$ref": format!("#/$defs/{}", child) }))
+            .collect();
+        match refs.len() {
+            1 => refs[0].clone(),
+            _ => json!({ "oneOf": refs }),
+        }
+    };
+
+    if flattened {
+        for child in e.children {
+            let child = find(spec, child);
+            let (name, property) = if SECTIONS.contains(&child.id) {
+                let items = one_of(child.children, true);
+                let property =
+                    json!({ "type": "array", "description": child.description, "items": items });
+                (child.name.to_string(), property)
+            } else if child.id == "profile" || child.id == "var" {
+                let items = one_of(&[child.id], false);
+                (
+                    format!("{}s", child.id),
+                    json!({ "type": "array", "items": items }),
+                )
+            } else {
+                (child.name.to_string(), one_of(&[child.id], false))
+            };
+            properties.insert(name, property);
+        }
+    } else if !e.children.is_empty() {
+        properties.insert(
+            "children".to_string(),
+            json!({ "type": "array", "items": one_of(e.children, true) }),
+        );
+    }
+
+    json!({
+        "type": "object",
+        "description": e.description,
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    })
+}
+
+/// Checks a profiles element against the schema, returning the problems found.
+pub fn validate(root: &Element) -> Vec<String> {
+    let spec = spec();
+    let mut problems = vec![];
+    let root_spec = find(&spec, ROOT);
+    if root.name == root_spec.name {
+        validate_element(&spec, root_spec, root, ROOT, &mut problems);
+    } else {
+        problems.push(format!(
+            "Root element should be {}, not {}",
+            ROOT, root.name
+        ));
+    }
+    problems
+}
+
+fn validate_element(
+    spec: &[ElementSpec],
+    e_spec: &ElementSpec,
+    e: &Element,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    for a in &e_spec.attributes {
+        match e.get_attribute(a.name, None) {
+            Some(value) if !value.contains("${") && !is_valid(a.kind, value) => {
+                problems.push(format!("{}: invalid {} {}", path, a.name, value))
+            }
+            None if a.required => problems.push(format!("{}: {} is missing", path, a.name)),
+            _ => {}
+        }
+    }
+    let mut names: Vec<_> = e.attributes.keys().map(|(name, _)| name).collect();
+    names.sort();
+    for name in names {
+        if !e_spec.attributes.iter().any(|a| a.name == name) {
+            problems.push(format!("{}: unknown attribute {}", path, name));
+        }
+    }
+
+    for child in &e.children {
+        match child {
+            Xml::ElementNode(child) => {
+                let child_path = format!("{}/{}", path, child.name);
+                let child_spec = e_spec
+                    .children
+                    .iter()
+                    .map(|id| find(spec, id))
+                    .find(|child_spec| child_spec.name == child.name);
+                match child_spec {
+                    Some(child_spec) => {
+                        validate_element(spec, child_spec, child, &child_path, problems)
+                    }
+                    None => problems.push(format!("{}: unknown element", child_path)),
+                }
+            }
+            Xml::CharacterNode(text) if !text.trim().is_empty() => {
+                problems.push(format!("{}: unexpected text {}", path, text.trim()))
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_valid(kind: Kind, value: &str) -> bool {
+    match kind {
+        Kind::Text => true,
+        Kind::KeyCode => keys::vk_code(value).is_some(),
+        Kind::Bool => value == "true" || value == "false",
+        Kind::Integer => value.parse::<i64>().is_ok(),
+        Kind::Number => value.parse::<f64>().is_ok(),
+        Kind::Duration => profiles::parse_duration(value, 60.0).is_ok(),
+        Kind::Choice(values) => values.contains(&value),
+    }
+}
+
+/// Runs `keymapper schema` and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    windows::attach_parent_console();
+
+    match print_schema(args) {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn print_schema(args: &[String]) -> Result<(), AppError> {
+    match args {
+        [format] if format == "xsd" => print!("{}", xsd()),
+        [format] if format == "json" => println!("{}", json_text()),
+        _ => return Err(AppError::new(USAGE)),
+    }
+    Ok(())
+}
+
+fn json_text() -> String {
+    serde_json::to_string_pretty(&json_schema()).expect("Schema is valid JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use crate::document::Document;
+
+    /// Problems xmllint finds with the XSD, None if it isn't installed.
+    fn xsd_problems(text: &str) -> Option<Vec<String>> {
+        let mut child = Command::new("xmllint")
+            .args(["--noout", "--schema", "resources/profiles.xsd", "-"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .ok()?;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        let problems = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter(|line| !line.ends_with("validates") && !line.ends_with("fails to validate"))
+            .map(str::to_string)
+            .collect();
+        Some(problems)
+    }
+
+    /// Problems with the JSON Schema of the document converted to TOML or YAML.
+    fn json_problems(root: &Element) -> Vec<String> {
+        let file = fs::read_to_string("resources/profiles.schema.json").unwrap();
+        let schema: Value = serde_json::from_str(&file).unwrap();
+        let document = serde_json::to_value(Document::from_element(root).unwrap()).unwrap();
+        jsonschema::validator_for(&schema)
+            .unwrap()
+            .iter_errors(&document)
+            .map(|err| err.to_string())
+            .collect()
+    }
+
+    /// Checks the document with the validator, the JSON Schema and the XSD.
+    fn assert_valid(text: &str, valid: bool) {
+        let root: Element = text.parse().unwrap();
+        assert_eq!(validate(&root).is_empty(), valid, "validator");
+        assert_eq!(json_problems(&root).is_empty(), valid, "JSON Schema");
+        match xsd_problems(text) {
+            Some(problems) => assert_eq!(problems.is_empty(), valid, "XSD: {:?}", problems),
+            None => eprintln!("xmllint is not available, XSD not checked"),
+        }
+    }
+
+    #[test]
+    fn published_schemas_are_current() {
+        let xsd_file = fs::read_to_string("resources/profiles.xsd").unwrap();
+        assert!(
+            xsd_file == xsd(),
+            "run keymapper schema xsd > resources/profiles.xsd"
+        );
+
+        let json_file = fs::read_to_string("resources/profiles.schema.json").unwrap();
+        assert!(
+            json_file.trim_end() == json_text(),
+            "run keymapper schema json > resources/profiles.schema.json"
+        );
+    }
+
+    #[test]
+    fn shipped_profiles_validate() {
+        let text = fs::read_to_string("resources/profiles.xml").unwrap();
+        let root: Element = text.parse().unwrap();
+        assert_eq!(validate(&root), Vec::<String>::new());
+        assert_eq!(json_problems(&root), Vec::<String>::new());
+        if let Some(problems) = xsd_problems(&text) {
+            assert_eq!(problems, Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn accept_key_codes_in_any_case() {
+        let binding = |code: &str| {
+            format!(
+                r#"<profiles version="3"><hotkeys><binding vk_code="{}"/></hotkeys></profiles>"#,
+                code
+            )
+        };
+        for code in &["Tab", "tab", "TAB", "a", "F1", "0x41", "0X41", "${key}"] {
+            assert_valid(&binding(code), true);
+        }
+        for code in &["Ctlr", "0x", "F25", ""] {
+            assert_valid(&binding(code), false);
+        }
+    }
+
+    #[test]
+    fn accept_durations_like_the_parser() {
+        let key = |delay: &str| {
+            format!(
+                r#"<profiles version="3"><hotkeys><binding vk_code="0x41"><key vk_code="0x42" delay="{}"/></binding></hotkeys></profiles>"#,
+                delay
+            )
+        };
+        let valid = ["50", "50ms", " 50 ", "50 ms", "1.5 s", "3f", " 3 f "];
+        let invalid = [
+            "5.", ".5", "1.2.3", "5 frames", "-5", "ms", "", "0x10", "5 m s",
+        ];
+        for delay in valid.iter().chain(&invalid) {
+            let parsed = profiles::parse_duration(delay, 60.0).is_ok();
+            assert_eq!(parsed, valid.contains(delay), "parse {:?}", delay);
+            assert_valid(&key(delay), parsed);
+        }
+    }
+
+    #[test]
+    fn report_schema_problems() {
+        let root: Element = r#"<profiles>
+            <profile name="Test" fsp="30">
+                <bindings>
+                    <binding vk_code="Ctlr" up="yes"><key/><jump/></binding>
+                </bindings>
+            </profile>
+        </profiles>"#
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            validate(&root),
+            vec![
+                "profiles/profile: unknown attribute fsp",
+                "profiles/profile/bindings/binding: invalid vk_code Ctlr",
+                "profiles/profile/bindings/binding: invalid up yes",
+                "profiles/profile/bindings/binding/key: vk_code is missing",
+                "profiles/profile/bindings/binding/jump: unknown element",
+            ]
+        );
+    }
+}
@@ -1,7 +1,64 @@
-pub struct Settings {}
+use std::collections::HashMap;
+
+use config::{Config, File, FileFormat};
+use serde::Deserialize;
+
+use crate::errors::AppError;
+use crate::profiles;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub debounce: DebounceSettings,
+    pub metrics: MetricsSettings,
+    pub macros: MacroSettings,
+}
+
+/// Keyboard chatter filter settings, all windows are in milliseconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DebounceSettings {
+    /// Window applied to every key, 0 disables the filter.
+    pub window: u64,
+    /// Per-key windows keyed by hex virtual key code.
+    pub keys: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    /// Address of the HTTP metrics endpoint, empty disables it.
+    pub address: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MacroSettings {
+    /// Seed of the delay jitter, a random one is used when missing.
+    pub seed: Option<u64>,
+}
 
 impl Settings {
-    pub fn load() -> Result<Settings, &'static str> {
-        Ok(Settings {})
+    /// Reads `application.conf` of the config directories, defaults apply without one.
+    pub fn load() -> Result<Settings, AppError> {
+        let mut builder = Config::builder();
+        if let Some(path) = profiles::config_file("application.conf") {
+            builder = builder.add_source(File::from(path.as_path()).format(FileFormat::Toml));
+        }
+        builder
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(|e| AppError::new(format!("Error loading application.conf: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_settings_works() {
+        let settings = Settings::load();
+        assert!(settings.is_ok());
     }
 }
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::profiles::{Throttle, ThrottleMode};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ThrottleDecision {
+    Pass,
+    Drop,
+    /// Pass once the given time has elapsed, at the end of the window.
+    Defer(Duration),
+}
+
+/// Throttle state of a single binding.
+#[derive(Debug, Default)]
+pub struct ThrottleState {
+    passed: VecDeque<Instant>,
+    /// End of the window of a trailing-edge event waiting to pass.
+    deadline: Option<Instant>,
+    /// Whether the last press passed, so that its release does too.
+    pressed: bool,
+}
+
+impl ThrottleState {
+    /// Checks a press, releases follow it with `check_release`.
+    pub fn check(&mut self, throttle: &Throttle, now: Instant) -> ThrottleDecision {
+        let decision = self.check_press(throttle, now);
+        self.pressed = decision != ThrottleDecision::Drop;
+        decision
+    }
+
+    /// Passes the release of a press that passed, after it if it is held back.
+    pub fn check_release(&mut self, now: Instant) -> ThrottleDecision {
+        if !mem::replace(&mut self.pressed, false) {
+            return ThrottleDecision::Drop;
+        }
+        match self.pending(now) {
+            Some(delay) => ThrottleDecision::Defer(delay),
+            None => ThrottleDecision::Pass,
+        }
+    }
+
+    fn check_press(&mut self, throttle: &Throttle, now: Instant) -> ThrottleDecision {
+        let limit = match throttle.mode {
+            ThrottleMode::Leading => 1,
+            ThrottleMode::Sliding { limit } => limit,
+            ThrottleMode::Trailing => {
+                return match self.pending(now) {
+                    // the waiting event stands for this one
+                    Some(_) => ThrottleDecision::Drop,
+                    None => {
+                        self.deadline = Some(now + throttle.window);
+                        ThrottleDecision::Defer(throttle.window)
+                    }
+                };
+            }
+        };
+
+        while let Some(&first) = self.passed.front() {
+            if now.duration_since(first) >= throttle.window {
+                self.passed.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.passed.len() < limit {
+            self.passed.push_back(now);
+            ThrottleDecision::Pass
+        } else {
+            ThrottleDecision::Drop
+        }
+    }
+
+    /// Time left until a trailing-edge event passes.
+    fn pending(&self, now: Instant) -> Option<Duration> {
+        self.deadline
+            .filter(|deadline| *deadline > now)
+            .map(|deadline| deadline - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(mode: ThrottleMode) -> Throttle {
+        Throttle {
+            window: Duration::from_millis(100),
+            mode,
+        }
+    }
+
+    #[test]
+    fn leading_drops_events_within_window() {
+        let throttle = throttle(ThrottleMode::Leading);
+        let mut state = ThrottleState::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(state.check(&throttle, at(0)), ThrottleDecision::Pass);
+        assert_eq!(state.check(&throttle, at(50)), ThrottleDecision::Drop);
+        assert_eq!(state.check(&throttle, at(99)), ThrottleDecision::Drop);
+        assert_eq!(state.check(&throttle, at(100)), ThrottleDecision::Pass);
+    }
+
+    #[test]
+    fn trailing_passes_once_at_end_of_window() {
+        let throttle = throttle(ThrottleMode::Trailing);
+        let mut state = ThrottleState::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(
+            state.check(&throttle, at(0)),
+            ThrottleDecision::Defer(Duration::from_millis(100))
+        );
+        assert_eq!(state.pending(at(30)), Some(Duration::from_millis(70)));
+        assert_eq!(state.check(&throttle, at(30)), ThrottleDecision::Drop);
+        assert_eq!(state.pending(at(100)), None);
+        assert_eq!(
+            state.check(&throttle, at(100)),
+            ThrottleDecision::Defer(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn sliding_limits_events_per_window() {
+        let throttle = throttle(ThrottleMode::Sliding { limit: 2 });
+        let mut state = ThrottleState::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(state.check(&throttle, at(0)), ThrottleDecision::Pass);
+        assert_eq!(state.check(&throttle, at(40)), ThrottleDecision::Pass);
+        assert_eq!(state.check(&throttle, at(80)), ThrottleDecision::Drop);
+        assert_eq!(state.check(&throttle, at(100)), ThrottleDecision::Pass);
+        assert_eq!(state.check(&throttle, at(120)), ThrottleDecision::Drop);
+        assert_eq!(state.check(&throttle, at(140)), ThrottleDecision::Pass);
+    }
+}
@@ -0,0 +1,119 @@
+//! Input events and the answer of hooks to them, the same on every platform.
+
+use serde::{Deserialize, Serialize};
+
+/// Flags of low-level keyboard events.
+const LLKHF_ALTDOWN: u32 = 0x20;
+const LLKHF_UP: u32 = 0x80;
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookAction {
+    Block,
+    Forward,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(tag = "device", rename_all = "snake_case")]
+pub enum InputEvent {
+    Keyboard(KeyboardEvent),
+    Mouse(MouseEvent),
+}
+
+impl InputEvent {
+    /// Whether a program sent the event rather than a device.
+    pub fn syntetic(&self) -> bool {
+        match self {
+            InputEvent::Keyboard(e) => e.syntetic(),
+            InputEvent::Mouse(e) => e.syntetic(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct KeyboardEvent {
+    pub vk_code: u32,
+    #[serde(default)]
+    pub flags: u32,
+    #[serde(default)]
+    pub extra: usize,
+}
+
+#[allow(dead_code)]
+impl KeyboardEvent {
+    pub fn new(vk_code: u32, up: bool) -> KeyboardEvent {
+        KeyboardEvent {
+            vk_code,
+            flags: if up { LLKHF_UP } else { 0 },
+            extra: 0,
+        }
+    }
+
+    pub fn alt(&self) -> bool {
+        self.flags & LLKHF_ALTDOWN > 0
+    }
+
+    pub fn up(&self) -> bool {
+        self.flags & LLKHF_UP > 0
+    }
+
+    pub fn syntetic(&self) -> bool {
+        self.extra != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MouseEvent {
+    Button {
+        #[serde(default)]
+        x: i32,
+        #[serde(default)]
+        y: i32,
+        button: MouseButton,
+        #[serde(default)]
+        up: bool,
+        #[serde(default)]
+        extra: usize,
+    },
+    #[serde(rename = "wheel")]
+    MouseWheel {
+        #[serde(default)]
+        x: i32,
+        #[serde(default)]
+        y: i32,
+        delta: i16,
+        #[serde(default)]
+        extra: usize,
+    },
+    #[serde(rename = "hwheel")]
+    MouseHWheel {
+        #[serde(default)]
+        x: i32,
+        #[serde(default)]
+        y: i32,
+        delta: i16,
+        #[serde(default)]
+        extra: usize,
+    },
+}
+
+impl MouseEvent {
+    pub fn syntetic(&self) -> bool {
+        match *self {
+            MouseEvent::Button { extra, .. }
+            | MouseEvent::MouseWheel { extra, .. }
+            | MouseEvent::MouseHWheel { extra, .. } => extra != 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    X1,
+    X2,
+}
@@ -8,6 +8,7 @@ use winapi::shared::windef::*;
 use winapi::um::winuser::*;
 
 use crate::util::*;
+use crate::windows::{HookAction, InputEvent, KeyboardEvent, MouseButton, MouseEvent};
 
 pub struct Hook {
     _hook_int: Vec<WeakCollectionItem<HookInternal>>,
@@ -74,18 +75,35 @@ impl Hook {
 
             let handler = move |_: i32, w_param: usize, l_param: isize| {
                 let mshs = unsafe { &*(l_param as *const MSLLHOOKSTRUCT) };
+                let (x, y) = (mshs.pt.x, mshs.pt.y);
+                let extra = mshs.dwExtraInfo;
+
+                let event = match w_param as u32 {
+                    WM_MOUSEWHEEL => MouseEvent::MouseWheel {
+                        x,
+                        y,
+                        delta: HIWORD(mshs.mouseData) as i16,
+                        extra,
+                    },
+                    WM_MOUSEHWHEEL => MouseEvent::MouseHWheel {
+                        x,
+                        y,
+                        delta: HIWORD(mshs.mouseData) as i16,
+                        extra,
+                    },
+                    message => match mouse_button(message, mshs.mouseData) {
+                        Some((button, up)) => MouseEvent::Button {
+                            x,
+                            y,
+                            button,
+                            up,
+                            extra,
+                        },
+                        None => return HookAction::Forward,
+                    },
+                };
 
-                match w_param as u32 {
-                    WM_MOUSEWHEEL => {
-                        let event = MouseEvent::MouseWheel {
-                            x: mshs.pt.x,
-                            y: mshs.pt.y,
-                            delta: HIWORD(mshs.mouseData) as i16,
-                        };
-                        handler(&event)
-                    }
-                    _ => HookAction::Forward,
-                }
+                handler(&event)
             };
 
             let hook = HookInternal {
@@ -114,46 +132,27 @@ impl Drop for HookInternal {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub enum HookAction {
-    Block,
-    Forward,
-}
-
-#[derive(Debug)]
-pub enum InputEvent {
-    Keyboard(KeyboardEvent),
-    Mouse(MouseEvent),
-}
-
-#[derive(Debug, Copy, Clone)]
-pub struct KeyboardEvent {
-    pub vk_code: u32,
-    pub flags: u32,
-    pub extra: usize,
-}
-
-#[allow(dead_code)]
-impl KeyboardEvent {
-    pub fn alt(&self) -> bool {
-        self.flags & LLKHF_ALTDOWN > 0
-    }
-
-    pub fn up(&self) -> bool {
-        self.flags & LLKHF_UP > 0
-    }
-
-    pub fn syntetic(&self) -> bool {
-        self.extra != 0
+/* PRIVATE */
+fn mouse_button(message: u32, mouse_data: u32) -> Option<(MouseButton, bool)> {
+    match message {
+        WM_LBUTTONDOWN => Some((MouseButton::Left, false)),
+        WM_LBUTTONUP => Some((MouseButton::Left, true)),
+        WM_RBUTTONDOWN => Some((MouseButton::Right, false)),
+        WM_RBUTTONUP => Some((MouseButton::Right, true)),
+        WM_MBUTTONDOWN => Some((MouseButton::Middle, false)),
+        WM_MBUTTONUP => Some((MouseButton::Middle, true)),
+        WM_XBUTTONDOWN | WM_XBUTTONUP => {
+            let button = match HIWORD(mouse_data) {
+                XBUTTON1 => MouseButton::X1,
+                XBUTTON2 => MouseButton::X2,
+                _ => return None,
+            };
+            Some((button, message == WM_XBUTTONUP))
+        }
+        _ => None,
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum MouseEvent {
-    MouseWheel { x: i32, y: i32, delta: i16 },
-}
-
-/* PRIVATE */
 unsafe extern "system" fn keyboard_hook_proc(n_code: i32, w_param: usize, l_param: isize) -> isize {
     base_hook_proc(&KEYBOARD_HOOKS, n_code, w_param, l_param)
 }
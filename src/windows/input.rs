@@ -2,6 +2,8 @@ use std::mem;
 
 use winapi::um::winuser::*;
 
+use crate::windows::MouseButton;
+
 pub fn send_input_key(virtual_key: i32, up: bool) {
     unsafe {
         let mut input = INPUT {
@@ -16,6 +18,75 @@ pub fn send_input_key(virtual_key: i32, up: bool) {
             time: 0,
         };
 
-        SendInput(1, &mut input, mem::size_of::<INPUT>() as i32);
+        send_input(input);
+    }
+}
+
+pub fn send_input_mouse_button(button: MouseButton, up: bool) {
+    let (flags, data) = match (button, up) {
+        (MouseButton::Left, false) => (MOUSEEVENTF_LEFTDOWN, 0),
+        (MouseButton::Left, true) => (MOUSEEVENTF_LEFTUP, 0),
+        (MouseButton::Right, false) => (MOUSEEVENTF_RIGHTDOWN, 0),
+        (MouseButton::Right, true) => (MOUSEEVENTF_RIGHTUP, 0),
+        (MouseButton::Middle, false) => (MOUSEEVENTF_MIDDLEDOWN, 0),
+        (MouseButton::Middle, true) => (MOUSEEVENTF_MIDDLEUP, 0),
+        (MouseButton::X1, false) => (MOUSEEVENTF_XDOWN, XBUTTON1),
+        (MouseButton::X1, true) => (MOUSEEVENTF_XUP, XBUTTON1),
+        (MouseButton::X2, false) => (MOUSEEVENTF_XDOWN, XBUTTON2),
+        (MouseButton::X2, true) => (MOUSEEVENTF_XUP, XBUTTON2),
+    };
+
+    send_input_mouse(0, 0, data as u32, flags);
+}
+
+/// Moves the pointer by (x, y) pixels, or to the (x, y) screen position when `absolute` is set.
+pub fn send_input_mouse_move(x: i32, y: i32, absolute: bool) {
+    if absolute {
+        // absolute coordinates are normalized to 0..65535 over the primary screen
+        let (w, h) = unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+        let x = normalize_coordinate(x, w);
+        let y = normalize_coordinate(y, h);
+        send_input_mouse(x, y, 0, MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE);
+    } else {
+        send_input_mouse(x, y, 0, MOUSEEVENTF_MOVE);
+    }
+}
+
+/// Maps a pixel position on a screen side of `size` pixels to 0..65535, off-screen ones to the edge.
+fn normalize_coordinate(position: i32, size: i32) -> i32 {
+    let normalized = i64::from(position) * 65535 / i64::from((size - 1).max(1));
+    normalized.clamp(0, 65535) as i32
+}
+
+/// Scrolls the wheel by `delta` (a multiple of 120 per notch), or tilts it if `horizontal` is set.
+pub fn send_input_mouse_wheel(delta: i32, horizontal: bool) {
+    let flags = if horizontal {
+        MOUSEEVENTF_HWHEEL
+    } else {
+        MOUSEEVENTF_WHEEL
+    };
+    send_input_mouse(0, 0, delta as u32, flags);
+}
+
+fn send_input_mouse(dx: i32, dy: i32, data: u32, flags: u32) {
+    unsafe {
+        let mut input = INPUT {
+            type_: INPUT_MOUSE,
+            u: std::mem::zeroed(),
+        };
+        *input.u.mi_mut() = MOUSEINPUT {
+            dx,
+            dy,
+            mouseData: data,
+            dwFlags: flags,
+            dwExtraInfo: 1,
+            time: 0,
+        };
+
+        send_input(input);
     }
 }
+
+unsafe fn send_input(mut input: INPUT) {
+    SendInput(1, &mut input, mem::size_of::<INPUT>() as i32);
+}
@@ -1,6 +1,8 @@
 use std::mem;
 use std::ptr;
 
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
 use winapi::um::winuser::*;
 
 pub fn message_loop() {
@@ -12,7 +14,20 @@ pub fn message_loop() {
     }
 }
 
-#[allow(dead_code)]
 pub fn post_quit_message() {
     unsafe { PostQuitMessage(0) };
 }
+
+pub fn current_thread_id() -> u32 {
+    unsafe { GetCurrentThreadId() }
+}
+
+/// Ends the message loop running on another thread.
+pub fn post_thread_quit_message(thread_id: u32) {
+    unsafe { PostThreadMessageW(thread_id, WM_QUIT, 0, 0) };
+}
+
+/// Lets command line modes print to the console they were started from.
+pub fn attach_parent_console() {
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
@@ -1,9 +1,27 @@
+mod event;
+#[cfg(windows)]
 mod hook;
+#[cfg(windows)]
 mod input;
+#[cfg(windows)]
 mod message;
+#[cfg(not(windows))]
+mod stub;
+#[cfg(windows)]
+mod timer;
+#[cfg(windows)]
 mod window;
 
+pub use self::event::*;
+#[cfg(windows)]
 pub use self::hook::*;
+#[cfg(windows)]
 pub use self::input::*;
+#[cfg(windows)]
 pub use self::message::*;
+#[cfg(not(windows))]
+pub use self::stub::*;
+#[cfg(windows)]
+pub use self::timer::*;
+#[cfg(windows)]
 pub use self::window::*;
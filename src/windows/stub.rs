@@ -0,0 +1,96 @@
+//! Stand-ins for the Windows API on other platforms, so that the crate builds and its tests
+//! run there: hooks never see any input, sent input goes nowhere and there are no windows.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::windows::{HookAction, InputEvent, KeyboardEvent, MouseButton, MouseEvent};
+
+pub struct Hook {}
+
+#[allow(dead_code)]
+impl Hook {
+    pub fn set_input_hook<H: FnMut(&InputEvent) -> HookAction + 'static>(_handler: H) -> Hook {
+        Hook {}
+    }
+
+    pub fn set_keyboard_hook<H: Fn(&KeyboardEvent) -> HookAction + 'static>(_handler: H) -> Hook {
+        Hook {}
+    }
+
+    pub fn set_mouse_hook<H: Fn(&MouseEvent) -> HookAction + 'static>(_handler: H) -> Hook {
+        Hook {}
+    }
+}
+
+pub struct Window {}
+
+#[allow(dead_code)]
+impl Window {
+    pub fn find(_name: &str) -> Vec<Window> {
+        vec![]
+    }
+
+    pub fn foreground() -> Option<Window> {
+        None
+    }
+
+    pub fn title(&self) -> String {
+        String::new()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        false
+    }
+
+    pub fn is_foreground(&self) -> bool {
+        false
+    }
+
+    pub fn is_full_screen(&self) -> bool {
+        false
+    }
+}
+
+pub fn send_input_key(_virtual_key: i32, _up: bool) {}
+
+pub fn send_input_mouse_button(_button: MouseButton, _up: bool) {}
+
+pub fn send_input_mouse_move(_x: i32, _y: i32, _absolute: bool) {}
+
+pub fn send_input_mouse_wheel(_delta: i32, _horizontal: bool) {}
+
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+/// Threads asked to end their message loop.
+static QUIT: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static QUIT_POSTED: Condvar = Condvar::new();
+
+thread_local!(static THREAD_ID: u32 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+
+/// Waits for a quit message, there are no others.
+pub fn message_loop() {
+    let id = current_thread_id();
+    let mut quit = QUIT.lock().unwrap();
+    while !quit.contains(&id) {
+        quit = QUIT_POSTED.wait(quit).unwrap();
+    }
+    quit.retain(|thread_id| *thread_id != id);
+}
+
+pub fn post_quit_message() {
+    post_thread_quit_message(current_thread_id());
+}
+
+pub fn current_thread_id() -> u32 {
+    THREAD_ID.with(|id| *id)
+}
+
+/// Ends the message loop running on another thread.
+pub fn post_thread_quit_message(thread_id: u32) {
+    QUIT.lock().unwrap().push(thread_id);
+    QUIT_POSTED.notify_all();
+}
+
+pub fn attach_parent_console() {}
+
+pub fn set_timer_resolution(_ms: u32) {}
@@ -0,0 +1,6 @@
+use winapi::um::timeapi::timeBeginPeriod;
+
+/// Raises the resolution of timers and sleeps of the whole process, in milliseconds.
+pub fn set_timer_resolution(ms: u32) {
+    unsafe { timeBeginPeriod(ms) };
+}
@@ -45,6 +45,12 @@ impl Window {
         }
     }
 
+    pub fn title(&self) -> String {
+        let mut buffer = [0u16; 512];
+        let len = unsafe { GetWindowTextW(self.handle, buffer.as_mut_ptr(), buffer.len() as i32) };
+        String::from_utf16_lossy(&buffer[..len.max(0) as usize])
+    }
+
     pub fn is_valid(&self) -> bool {
         unsafe { IsWindow(self.handle) > 0 }
     }